@@ -1,6 +1,6 @@
 use bevy::audio::{PlaybackMode, Volume};
 
-use super::*;
+use super::{profile::Profile, *};
 
 pub struct Plugin;
 
@@ -18,24 +18,24 @@ impl bevy::app::Plugin for Plugin {
     }
 }
 
-fn play_bgm(mut commands: Commands, audio_assets: Res<AudioAssets>) {
+fn play_bgm(mut commands: Commands, audio_assets: Res<AudioAssets>, profile: Res<Profile>) {
     commands.spawn(AudioBundle {
         source: audio_assets.bgm.clone(),
         settings: PlaybackSettings {
             mode: PlaybackMode::Loop,
-            volume: Volume::new(0.03),
+            volume: Volume::new(profile.settings.music_volume),
             ..default()
         },
     });
 }
 
-pub fn play_sfx(mut events: EventReader<PlaySFX>, mut commands: Commands) {
+pub fn play_sfx(mut events: EventReader<PlaySFX>, mut commands: Commands, profile: Res<Profile>) {
     for event in events.read() {
         commands.spawn(AudioBundle {
             source: event.source.clone(),
             settings: PlaybackSettings {
                 mode: PlaybackMode::Despawn,
-                volume: event.volume,
+                volume: Volume::new(event.volume.get() * profile.settings.sfx_volume),
                 ..default()
             },
         });
@@ -57,7 +57,7 @@ struct CharacterBundle {
 fn setup_celebration(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    image_assets: Res<ImageAssets>,
+    characters: Res<character::Characters>,
 ) {
     // 半透明背景
     commands.spawn((
@@ -95,21 +95,16 @@ fn setup_celebration(
         CelebrationEntity,
     ));
 
-    // 四个角的角色
-    let characters = [
-        (Val::Percent(5.0), Val::Percent(5.0), CharacterType::Duck),//左下
-        (Val::Percent(80.0), Val::Percent(5.0), CharacterType::Cat),//右下
-        (Val::Percent(5.0), Val::Percent(70.0), CharacterType::Bunny),//左上
-        (Val::Percent(80.0), Val::Percent(70.0), CharacterType::Chick),//右上
+    // 四个角的角色，按 characters.ron 的顺序
+    let corners = [
+        (Val::Percent(5.0), Val::Percent(5.0)),//左下
+        (Val::Percent(80.0), Val::Percent(5.0)),//右下
+        (Val::Percent(5.0), Val::Percent(70.0)),//左上
+        (Val::Percent(80.0), Val::Percent(70.0)),//右上
     ];
 
-    for (left, bottom, char_type) in characters {
-        let texture = match char_type {
-            CharacterType::Duck => image_assets.duck.clone(),
-            CharacterType::Cat => image_assets.cat.clone(),
-            CharacterType::Bunny => image_assets.bunny.clone(),
-            CharacterType::Chick => image_assets.chick.clone(),
-        };
+    for ((left, bottom), char_type) in corners.into_iter().zip(characters.all().cycle()) {
+        let texture = characters.sprite(char_type, false);
 
         commands.spawn(CharacterBundle {
             image: ImageBundle {
@@ -209,7 +204,7 @@ fn setup_celebration(
 
 fn character_animation(
     time: Res<Time>,
-    image_assets: Res<ImageAssets>,
+    characters: Res<character::Characters>,
     mut query: Query<(&mut CelebratingCharacter, &mut UiImage)>,
 ) {
     for (mut character, mut ui_image) in query.iter_mut() {
@@ -217,29 +212,19 @@ fn character_animation(
         if character.timer.just_finished() {
             character.is_stuffed = !character.is_stuffed;
             
-            ui_image.texture = match (character.character_type, character.is_stuffed) {
-                (CharacterType::Duck, false) => image_assets.duck.clone(),
-                (CharacterType::Duck, true) => image_assets.stuffed_duck.clone(),
-                (CharacterType::Cat, false) => image_assets.cat.clone(),
-                (CharacterType::Cat, true) => image_assets.stuffed_cat.clone(),
-                (CharacterType::Bunny, false) => image_assets.bunny.clone(),
-                (CharacterType::Bunny, true) => image_assets.stuffed_bunny.clone(),
-                (CharacterType::Chick, false) => image_assets.chick.clone(),
-                (CharacterType::Chick, true) => image_assets.stuffed_chick.clone(),
-            };
+            ui_image.texture = characters.sprite(character.character_type, character.is_stuffed);
         }
     }
 }
 
-use renet::RenetClient;
-use crate::networking::ClientMessage;
+use crate::networking::{ClientMessage, ServerLink};
 
 fn menu_button_interaction(
     mut interaction_query: Query<(&Interaction, &mut BackgroundColor), (Changed<Interaction>, With<MenuButton>)>,
     //mut level_index: ResMut<CurrentLevelIndex>,
     //mut commands: Commands,
     //mut next_state: ResMut<NextState<GameStates>>,
-    mut client: Option<ResMut<RenetClient>>,
+    mut link: ServerLink,
 ) {
     for (interaction, mut color) in &mut interaction_query {
         match *interaction {
@@ -247,12 +232,7 @@ fn menu_button_interaction(
                 *color = PRESSED_BUTTON.into();
                 
                 // 重置关卡索引
-                if let Some(client) = &mut client {
-                    let message = bincode::serialize(&ClientMessage::StateChangeRequest(
-                        GameStates::GameMenu
-                    )).unwrap();
-                    client.send_message(0, message); // 使用可靠通道
-                } 
+                link.send(ClientMessage::StateChangeRequest(GameStates::GameMenu));
 
             }
             Interaction::Hovered => {
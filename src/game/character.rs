@@ -0,0 +1,291 @@
+use std::sync::Mutex;
+
+use bevy::asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+use super::*;
+
+// 角色都写在 assets/characters.ron 里：名字、贴图、饭量和能力。
+// 加一个角色只要在文件里加一项，再放好两张贴图。
+// 这个文件和图片一样在 Loading 时读进来，读完以后才有 Characters 资源
+
+pub struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<CharacterList>()
+            .init_asset_loader::<CharacterListLoader>()
+            .configure_loading_state(
+                LoadingStateConfig::new(GameStates::Loading)
+                    .load_collection::<CharacterAssets>()
+                    .init_resource::<Characters>(),
+            );
+    }
+}
+
+/// 角色的特殊能力，在滑行规则里生效
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum Ability {
+    /// 按住 Shift 移动时滑一格就停下
+    StopEarly,
+    /// 按住 Shift 移动时跳过旁边的一格
+    Hop,
+    /// 停在要碎的冰上也不会掉下去
+    LightFeet,
+    /// 踩碎要碎的冰，游到对岸
+    Swim,
+}
+
+/// 一种角色的数据
+#[derive(Clone, Debug, Deserialize)]
+pub struct CharacterStats {
+    pub id: String,
+    pub name: String,
+    pub sprite: String,
+    /// 吃饱以后的贴图
+    pub stuffed_sprite: String,
+    /// 吃几个面包就饱了
+    pub belly_capacity: u32,
+    pub ability: Ability,
+    /// 每关能用几次，None 表示不限
+    #[serde(default)]
+    pub ability_uses: Option<u32>,
+    /// 选角色时显示的说明
+    pub description: String,
+}
+
+impl CharacterStats {
+    /// 每关能用几次能力，不限次数时是 u32::MAX
+    pub fn ability_uses(&self) -> u32 {
+        self.ability_uses.unwrap_or(u32::MAX)
+    }
+}
+
+/// id 最长的字节数，CharacterType 把 id 直接存在里面
+const MAX_ID_LEN: usize = 15;
+
+/// 角色的 id，和 characters.ron 里写的一样。存档、录像和联网时都按 id 保存，
+/// 所以调整文件里的顺序不影响旧存档。空的 id（`default()`）和认不出的 id 都当作文件里的第一个角色
+#[derive(Component, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CharacterType {
+    len: u8,
+    bytes: [u8; MAX_ID_LEN],
+}
+
+impl CharacterType {
+    /// id 太长或者是空的时候返回 None
+    pub fn from_id(id: &str) -> Option<CharacterType> {
+        if id.is_empty() || id.len() > MAX_ID_LEN {
+            return None;
+        }
+        let mut bytes = [0; MAX_ID_LEN];
+        bytes[..id.len()].copy_from_slice(id.as_bytes());
+        Some(CharacterType { len: id.len() as u8, bytes })
+    }
+
+    pub fn id(&self) -> &str {
+        std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+    }
+}
+
+impl std::fmt::Debug for CharacterType {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.id() {
+            "" => formatter.write_str("Default"),
+            id => formatter.write_str(id),
+        }
+    }
+}
+
+// RON 只能把 &'static str 写成标识符，每个 id 只留一份
+fn static_id(id: &str) -> &'static str {
+    static IDS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+    let mut ids = IDS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(&known) = ids.iter().find(|&&known| known == id) {
+        return known;
+    }
+    let leaked: &'static str = Box::leak(id.to_owned().into_boxed_str());
+    ids.push(leaked);
+    leaked
+}
+
+// RON 里写成标识符，和以前枚举的写法一样，旧存档还能读；bincode 里是字符串
+impl Serialize for CharacterType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            // 标识符不能是空的，读回来时认不出 Default，还是第一个角色
+            serializer.serialize_unit_variant("CharacterType", 0, static_id(&format!("{:?}", self)))
+        } else {
+            serializer.serialize_str(self.id())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CharacterType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct IdVisitor;
+
+        impl de::Visitor<'_> for IdVisitor {
+            type Value = CharacterType;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a character id from assets/characters.ron")
+            }
+
+            fn visit_str<E: de::Error>(self, id: &str) -> Result<CharacterType, E> {
+                // 联网消息里的默认角色是空字符串
+                if id.is_empty() {
+                    return Ok(CharacterType::default());
+                }
+                CharacterType::from_id(id).ok_or_else(|| E::custom(format!("character id {} is too long", id)))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_identifier(IdVisitor)
+        } else {
+            deserializer.deserialize_str(IdVisitor)
+        }
+    }
+}
+
+/// assets/characters.ron 的内容
+#[derive(Asset, TypePath, Deserialize)]
+#[serde(transparent)]
+pub struct CharacterList(Vec<CharacterStats>);
+
+#[derive(Error, Debug)]
+pub enum CharacterListError {
+    #[error("Fail to read the character list: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("The character list is invalid: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("The character list is empty")]
+    Empty,
+    #[error("Character id {0:?} must be 1 to {MAX_ID_LEN} bytes long")]
+    BadId(String),
+}
+
+impl CharacterList {
+    pub fn parse(content: &str) -> Result<CharacterList, CharacterListError> {
+        let list: CharacterList = ron::from_str(content)?;
+        if list.0.is_empty() {
+            return Err(CharacterListError::Empty);
+        }
+        if let Some(stats) = list.0.iter().find(|stats| CharacterType::from_id(&stats.id).is_none()) {
+            return Err(CharacterListError::BadId(stats.id.clone()));
+        }
+        Ok(list)
+    }
+}
+
+#[derive(Default)]
+struct CharacterListLoader;
+
+impl AssetLoader for CharacterListLoader {
+    type Asset = CharacterList;
+    type Settings = ();
+    type Error = CharacterListError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<CharacterList, CharacterListError> {
+        let mut content = String::new();
+        reader.read_to_string(&mut content).await?;
+        CharacterList::parse(&content)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["characters.ron"]
+    }
+}
+
+#[derive(AssetCollection, Resource)]
+struct CharacterAssets {
+    #[asset(path = "characters.ron")]
+    list: Handle<CharacterList>,
+}
+
+/// 所有角色的数据和两张贴图，顺序和 characters.ron 一样
+#[derive(Resource)]
+pub struct Characters {
+    stats: Vec<CharacterStats>,
+    sprites: Vec<(Handle<Image>, Handle<Image>)>,
+}
+
+impl FromWorld for Characters {
+    fn from_world(world: &mut World) -> Self {
+        let handle = world.resource::<CharacterAssets>().list.clone();
+        let stats = world
+            .resource_mut::<Assets<CharacterList>>()
+            .remove(&handle)
+            .expect("characters.ron is loaded before Characters")
+            .0;
+        let asset_server = world.resource::<AssetServer>();
+        Characters::new(stats, |path| asset_server.load(path.to_owned()))
+    }
+}
+
+impl Characters {
+    /// `load` 按路径加载贴图
+    pub fn new(stats: Vec<CharacterStats>, mut load: impl FnMut(&str) -> Handle<Image>) -> Self {
+        let sprites = stats
+            .iter()
+            .map(|stats| (load(&stats.sprite), load(&stats.stuffed_sprite)))
+            .collect();
+        Characters { stats, sprites }
+    }
+
+    pub fn all(&self) -> impl Iterator<Item = CharacterType> + Clone + '_ {
+        self.stats.iter().filter_map(|stats| CharacterType::from_id(&stats.id))
+    }
+
+    fn index(&self, character: CharacterType) -> usize {
+        self.stats.iter().position(|stats| stats.id == character.id()).unwrap_or(0)
+    }
+
+    pub fn stats(&self, character: CharacterType) -> &CharacterStats {
+        &self.stats[self.index(character)]
+    }
+
+    pub fn sprite(&self, character: CharacterType, stuffed: bool) -> Handle<Image> {
+        let (normal, full) = &self.sprites[self.index(character)];
+        if stuffed {
+            full.clone()
+        } else {
+            normal.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// assets/characters.ron 里的角色，没有贴图
+    pub(crate) fn characters() -> Characters {
+        let list = CharacterList::parse(include_str!("../../assets/characters.ron")).unwrap();
+        Characters::new(list.0, |_| Handle::default())
+    }
+
+    #[test]
+    fn ids_survive_ron_and_bincode() {
+        let characters = characters();
+        for character in characters.all() {
+            let text = ron::to_string(&character).unwrap();
+            assert_eq!(text, characters.stats(character).id);
+            assert_eq!(ron::from_str::<CharacterType>(&text).unwrap(), character);
+            let bytes = bincode::serialize(&character).unwrap();
+            assert_eq!(bincode::deserialize::<CharacterType>(&bytes).unwrap(), character);
+        }
+        // 默认角色和认不出的角色都是文件里的第一个
+        let first = characters.all().next().unwrap();
+        assert_eq!(characters.stats(CharacterType::default()).id, first.id());
+        let unknown = ron::from_str::<CharacterType>("Dragon").unwrap();
+        assert_eq!(characters.stats(unknown).id, first.id());
+    }
+}
@@ -1,7 +1,8 @@
 use super::{player::CommonDuck, *};
-use crate::game::player::{Player1,Player2};
-use bevy::{input::mouse::MouseButtonInput, window::PrimaryWindow};
-use crate::game::{CharacterType,SelectedCharacters};
+use crate::game::player::{can_select, Player1, Player2, NEUTRAL};
+use bevy::{input::{mouse::MouseButtonInput, ButtonState}, window::PrimaryWindow};
+use crate::game::CharacterType;
+use crate::networking::{ClientMessage, InboundServerMessage, LocalSeat, ServerLink, ServerMessage};
 pub struct Plugin;
 
 impl bevy::app::Plugin for Plugin {
@@ -11,7 +12,14 @@ impl bevy::app::Plugin for Plugin {
                 Update,
                 get_cursor_position.run_if(in_state(GameStates::Next)),
             )
-            .add_systems(Update, click_detection.run_if(in_state(GameStates::Next)));
+            // 切换小动物要经过服务器，双方看到的才一致；录像里的切换由 replay 自己处理
+            .add_systems(
+                Update,
+                (click_detection, cycle_active_duck)
+                    .run_if(in_state(GameStates::Next))
+                    .run_if(not(resource_equals(PlayMode::Replay))),
+            )
+            .add_systems(Update, apply_duck_selection.run_if(in_state(GameStates::Next)));
     }
 }
 
@@ -33,6 +41,7 @@ fn get_cursor_position(
     camera_query: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
     duck_query: Query<(&CommonDuck, &Transform), (With<CommonDuck>, Without<Player1>,Without<Player2>)>,
     arrow_query: Query<Entity, (With<ArrowHint>, Without<Parent>)>,
+    play_mode: Res<PlayMode>,
 ) {
     let (camera, camera_transform) = camera_query.single();
     let window = window_query.get_single().unwrap();
@@ -46,7 +55,11 @@ fn get_cursor_position(
             commands.entity(entity).despawn();
         }
         // Hover cursor on the duck, show arrow hint
-        for (_, transform) in duck_query.iter() {
+        // Nobody can pick a neutral duck except in single player
+        for (_, transform) in duck_query
+            .iter()
+            .filter(|(duck, _)| *play_mode == PlayMode::Solo || duck.owner != NEUTRAL)
+        {
             //let duck_position_v3 = logic_position_to_translation(duck.logic_position);
             let duck_position_v3=transform.translation;
             let duck_position: Vec2 = Vec2 {
@@ -80,99 +93,147 @@ fn get_cursor_position(
 #[derive(Component)]
 pub struct CharacterTag(pub CharacterType);
 
+// Left click picks a duck for Player1, right click for Player2. Online both
+// buttons pick for this client's own seat
 pub fn click_detection(
-    mut commands: Commands,
     // event
     mut mouse_button_input_events: EventReader<MouseButtonInput>,
     // query
-    duck_query: Query<(&CommonDuck, &CharacterTag,Entity), (With<CommonDuck>, Without<Player1>,Without<Player2>)>,
-    player1_query: Query<Entity, With<Player1>>,
-    player2_query: Query<Entity, With<Player2>>,
-    arrow_hint_query: Query<Entity, (With<ArrowHint>, With<Parent>)>,
+    duck_query: Query<&CommonDuck, (Without<Player1>, Without<Player2>)>,
     // resource
     cursor_position: Res<CursorPosition>,
-    image_assets: Res<ImageAssets>,
-    mut selected_characters: ResMut<SelectedCharacters>,
+    play_mode: Res<PlayMode>,
+    seat: Option<Res<LocalSeat>>,
+    mut link: ServerLink,
 ) {
+    let online_seat = seat.and_then(|seat| seat.0);
     for event in mouse_button_input_events.read() {
-        
-        //player1
-        if event.button ==MouseButton::Left {
-            for (duck, charactertag,entity) in duck_query.iter() {
-                let duck_position_v3 = logic_position_to_translation(duck.logic_position);
-                let duck_position: Vec2 = Vec2 {
-                    x: duck_position_v3.x,
-                    y: duck_position_v3.y,
-                };
-                if (cursor_position.0 - duck_position).length() < DISTANCE{
-                    selected_characters.player1 = Some(charactertag.0);
-                    commands
-                        .entity(entity)
-                        .insert(Player1)
-                        .with_children(|parent| {
-                            parent.spawn((
-                                SpriteBundle {
-                                    transform: Transform {
-                                        translation: Vec3::new(0.0, 500.0, 1.0),
-                                        ..default()
-                                    },
-                                    texture: image_assets.arrow2.clone(),
-                                    ..default()
-                                },
-                                ArrowHint,
-                                level::Object,
-                            ));
-                        });
-                    // Clear the previous player1
-                    for entity in player1_query.iter() {
-                        commands.entity(entity).remove::<Player1>();
-                        commands.entity(entity).clear_children();
-                    }
-                    // Clear the previous arrow hint
-                    for entity in arrow_hint_query.iter() {
-                        commands.entity(entity).despawn();
-                    }
-                }
-            }
+        if event.state != ButtonState::Pressed {
+            continue;
         }
-            
-        if event.button == MouseButton::Right{
-            for (duck, charactertag,entity) in duck_query.iter() {
-                let duck_position_v3 = logic_position_to_translation(duck.logic_position);
-                let duck_position: Vec2 = Vec2 {
-                    x: duck_position_v3.x,
-                    y: duck_position_v3.y,
-                };
-                if (cursor_position.0 - duck_position).length() < DISTANCE{
-                    selected_characters.player2 = Some(charactertag.0);
-                    commands
-                        .entity(entity)
-                        .insert(Player2)
-                        .with_children(|parent| {
-                            parent.spawn((
-                                SpriteBundle {
-                                    transform: Transform {
-                                        translation: Vec3::new(0.0, 500.0, 1.0),
-                                        ..default()
-                                    },
-                                    texture: image_assets.arrow.clone(),
-                                    ..default()
-                                },
-                                ArrowHint,
-                                level::Object,
-                            ));
-                        });
-                    // Clear the previous player
-                    for entity in player2_query.iter() {
-                        commands.entity(entity).remove::<Player2>();
-                        commands.entity(entity).clear_children();
-                    }
-                    // Clear the previous arrow hint
-                    for entity in arrow_hint_query.iter() {
-                        commands.entity(entity).despawn();
-                    }
-                }
-            }
+        let player_id = match event.button {
+            MouseButton::Left | MouseButton::Right if *play_mode == PlayMode::Online => match online_seat {
+                Some(seat) => seat,
+                None => continue,
+            },
+            MouseButton::Left => 1,
+            // There is no player2 in single player
+            MouseButton::Right if *play_mode != PlayMode::Solo => 2,
+            _ => continue,
+        };
+        let clicked = duck_query.iter().find(|duck| {
+            let duck_position = logic_position_to_translation(duck.logic_position).truncate();
+            (cursor_position.0 - duck_position).length() < DISTANCE
+        });
+        if let Some(duck) = clicked.filter(|duck| can_select(duck, player_id, *play_mode)) {
+            link.send(ClientMessage::SelectDuck {
+                player_id,
+                position: duck.logic_position,
+            });
+        }
+    }
+}
+
+// Tab switches Player1 to the next duck it owns, / does the same for Player2.
+// Online both keys switch this client's own seat
+fn cycle_active_duck(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    duck_query: Query<(&CommonDuck, Has<Player1>, Has<Player2>)>,
+    play_mode: Res<PlayMode>,
+    seat: Option<Res<LocalSeat>>,
+    mut link: ServerLink,
+) {
+    let bindings = match (*play_mode, seat.and_then(|seat| seat.0)) {
+        (PlayMode::Online, Some(seat)) => vec![(seat, KeyCode::Tab), (seat, KeyCode::Slash)],
+        (PlayMode::Online, None) => Vec::new(),
+        (PlayMode::Solo, _) => vec![(1, KeyCode::Tab)],
+        _ => vec![(1, KeyCode::Tab), (2, KeyCode::Slash)],
+    };
+    for (player_id, key) in bindings {
+        if !keyboard.just_pressed(key) {
+            continue;
+        }
+        let mut ducks: Vec<(&CommonDuck, bool)> = duck_query
+            .iter()
+            .filter(|(duck, _, _)| can_select(duck, player_id, *play_mode))
+            .map(|(duck, has_player1, has_player2)| {
+                (duck, if player_id == 1 { has_player1 } else { has_player2 })
+            })
+            .collect();
+        if ducks.len() < 2 {
+            continue;
+        }
+        // Cycle in reading order
+        ducks.sort_by_key(|(duck, _)| duck.logic_position);
+        let current = ducks.iter().position(|(_, active)| *active).unwrap_or(ducks.len() - 1);
+        let next = ducks[(current + 1) % ducks.len()].0;
+        link.send(ClientMessage::SelectDuck {
+            player_id,
+            position: next.logic_position,
+        });
+    }
+}
+
+// The server agreed on a switch, move the player's marker over
+fn apply_duck_selection(
+    mut commands: Commands,
+    mut inbound: EventReader<InboundServerMessage>,
+    duck_query: Query<(Entity, &CommonDuck, Has<Player1>, Has<Player2>)>,
+    image_assets: Res<ImageAssets>,
+    play_mode: Res<PlayMode>,
+) {
+    for InboundServerMessage(message) in inbound.read() {
+        let ServerMessage::DuckSelected { player_id, position } = *message else {
+            continue;
+        };
+        let allowed = duck_query.iter().any(|(_, duck, _, _)| {
+            duck.logic_position == position && can_select(duck, player_id, *play_mode)
+        });
+        if allowed {
+            hand_over(&mut commands, &image_assets, &duck_query, position, player_id);
+        }
+    }
+}
+
+// Move the player's marker to the duck at `from`
+pub fn hand_over(
+    commands: &mut Commands,
+    image_assets: &Res<ImageAssets>,
+    duck_query: &Query<(Entity, &CommonDuck, Has<Player1>, Has<Player2>)>,
+    from: (usize, usize),
+    player_id: u8,
+) {
+    for (entity, duck, has_player1, has_player2) in duck_query.iter() {
+        let holds_marker = if player_id == 1 { has_player1 } else { has_player2 };
+        if holds_marker || duck.logic_position == from {
+            commands
+                .entity(entity)
+                .remove::<(Player1, Player2)>()
+                .despawn_descendants();
         }
+        if duck.logic_position != from {
+            continue;
+        }
+        let arrow = if player_id == 1 {
+            commands.entity(entity).insert(Player1);
+            image_assets.arrow2.clone()
+        } else {
+            commands.entity(entity).insert(Player2);
+            image_assets.arrow.clone()
+        };
+        commands.entity(entity).with_children(|parent| {
+            parent.spawn((
+                SpriteBundle {
+                    transform: Transform {
+                        translation: Vec3::new(0.0, 500.0, 1.0),
+                        ..default()
+                    },
+                    texture: arrow,
+                    ..default()
+                },
+                ArrowHint,
+                level::Object,
+            ));
+        });
     }
 }
@@ -0,0 +1,648 @@
+use std::{fs, path::Path};
+
+use bevy::{ecs::system::SystemParam, window::PrimaryWindow};
+
+use super::{
+    character::Characters,
+    level::{
+        initial_record, load_level, spawn_sprites, BreadCount, CurrentLevelIndex, CustomLevel, CustomLevelMeta, Level, LevelMeta,
+        Levels, Switches, SymbolType,
+    },
+    level_select::clear_level_scene,
+    *,
+};
+use crate::networking::ai::AiOpponent;
+
+// Designing levels inside the game. The board is drawn with the same
+// sprites as the real game, can be test-played right away and is saved in
+// the same .txt format as the built-in levels. The levels are compiled into
+// the game, so a saved level only shows up after adding it to `Levels` and
+// building again.
+
+pub struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditorLevel>()
+            .add_systems(
+                OnEnter(GameStates::Editor),
+                (clear_level_scene, start_editing, setup_editor_ui).chain(),
+            )
+            .add_systems(
+                Update,
+                (
+                    editor_shortcuts,
+                    palette_interaction,
+                    editor_button_interaction,
+                    paint_tiles,
+                    redraw_level,
+                    update_editor_ui,
+                )
+                    .chain()
+                    .run_if(in_state(GameStates::Editor)),
+            )
+            .add_systems(OnExit(GameStates::Editor), cleanup_editor)
+            .add_systems(
+                Update,
+                leave_test_play
+                    .run_if(in_state(GameStates::Next))
+                    .run_if(resource_exists::<EditorTestPlay>),
+            );
+    }
+}
+
+const LEVEL_DIR: &str = "assets/levels";
+const MIN_SIZE: usize = 3;
+// Bigger boards don't fit on the screen
+const MAX_ROWS: usize = 10;
+const MAX_COLUMNS: usize = 20;
+const EMPTY: char = ' ';
+
+// A brush can paint a few variants of the same thing, picking it again
+// switches to the next one. No symbols erases the cell
+const PALETTE: &[(&str, &[SymbolType])] = &[
+    ("Wall", &[SymbolType::Wall]),
+    ("Ice", &[SymbolType::Ice]),
+    (
+        "Breaking",
+        &[SymbolType::BreakingIce, SymbolType::CrackedIce, SymbolType::SturdyIce],
+    ),
+    ("Water", &[SymbolType::BrokenIce]),
+    (
+        "Bread",
+        &[SymbolType::BreadOnIce, SymbolType::GoldenBread, SymbolType::HeavyBread, SymbolType::Pepper],
+    ),
+    ("Duck", &[SymbolType::DuckOnIce]),
+    (
+        "Arrow",
+        &[SymbolType::ArrowRight, SymbolType::ArrowDown, SymbolType::ArrowLeft, SymbolType::ArrowUp],
+    ),
+    ("Portal", &[SymbolType::Portal(1)]),
+    ("Block", &[SymbolType::IceBlock]),
+    (
+        "Switch",
+        &[SymbolType::Plate, SymbolType::TogglePlate, SymbolType::ClosedGate, SymbolType::OpenGate],
+    ),
+    ("Erase", &[]),
+];
+// Shortcut and its label for each palette entry, in the same order
+const PALETTE_KEYS: &[(KeyCode, &str)] = &[
+    (KeyCode::Digit1, "1"),
+    (KeyCode::Digit2, "2"),
+    (KeyCode::Digit3, "3"),
+    (KeyCode::Digit4, "4"),
+    (KeyCode::Digit5, "5"),
+    (KeyCode::Digit6, "6"),
+    (KeyCode::Digit7, "7"),
+    (KeyCode::Digit8, "8"),
+    (KeyCode::Digit9, "9"),
+    (KeyCode::Digit0, "0"),
+    (KeyCode::KeyE, "E"),
+];
+
+const NORMAL_BUTTON: Color = MY_ORANGE;
+const HOVERED_BUTTON: Color =
+    Color::srgb(222.0 / 255.0 + 0.1, 112.0 / 255.0 + 0.1, 40.0 / 255.0 + 0.1);
+const PRESSED_BUTTON: Color = Color::srgb(0.75, 0.75, 0.75);
+const SELECTED_BRUSH: Color = MY_BROWN;
+const CANVAS_COLOR: Color = Color::srgb(60.0 / 255.0, 63.0 / 255.0, 63.0 / 255.0);
+
+/// 编辑中的关卡，试玩回来以后还在
+#[derive(Resource, Default)]
+pub struct EditorLevel {
+    grid: Vec<Vec<char>>,
+    /// Par, channels and owners of the level being edited
+    meta: LevelMeta,
+    brush: usize,
+    variant: usize,
+    status: String,
+}
+
+impl EditorLevel {
+    fn rows(&self) -> usize {
+        self.grid.len()
+    }
+
+    fn columns(&self) -> usize {
+        self.grid.first().map_or(0, |row| row.len())
+    }
+
+    // Rows in level files can have different lengths, the editor keeps a rectangle
+    fn load(&mut self, level: &Level) {
+        let columns = level.0.iter().map(|row| row.len()).max().unwrap_or(0).clamp(MIN_SIZE, MAX_COLUMNS);
+        self.grid = level
+            .0
+            .iter()
+            .take(MAX_ROWS)
+            .map(|row| {
+                let mut row: Vec<char> = row.iter().copied().take(columns).collect();
+                row.resize(columns, EMPTY);
+                row
+            })
+            .collect();
+        while self.grid.len() < MIN_SIZE {
+            self.grid.push(vec![EMPTY; columns]);
+        }
+    }
+
+    fn resize(&mut self, rows: usize, columns: usize) {
+        let rows = rows.clamp(MIN_SIZE, MAX_ROWS);
+        let columns = columns.clamp(MIN_SIZE, MAX_COLUMNS);
+        if rows == self.rows() && columns == self.columns() {
+            return;
+        }
+        // New cells are walls, the usual border of a level
+        self.grid.resize(rows, vec![SymbolType::Wall.get_symbol(); columns]);
+        for row in self.grid.iter_mut() {
+            row.resize(columns, SymbolType::Wall.get_symbol());
+        }
+    }
+
+    fn pick_brush(&mut self, index: usize) {
+        if index == self.brush {
+            self.variant = (self.variant + 1) % PALETTE[index].1.len().max(1);
+        } else {
+            self.brush = index;
+            self.variant = 0;
+        }
+    }
+
+    fn brush_label(&self) -> String {
+        let name = PALETTE[self.brush].0;
+        match self.brush_symbol() {
+            Some(EMPTY) | None => name.to_string(),
+            Some(symbol) => format!("{} {}", name, symbol),
+        }
+    }
+
+    // None when there is nothing left to paint, i.e. all portal pairs are used
+    fn brush_symbol(&self) -> Option<char> {
+        match PALETTE[self.brush].1.get(self.variant) {
+            Some(SymbolType::Portal(_)) => self.free_portal().map(SymbolType::get_symbol),
+            Some(symbol) => Some(symbol.get_symbol()),
+            None => Some(EMPTY),
+        }
+    }
+
+    // Portals are painted into the first pair that still misses a cell
+    fn free_portal(&self) -> Option<SymbolType> {
+        (1..=9).map(SymbolType::Portal).find(|portal| {
+            let symbol = portal.get_symbol();
+            self.grid.iter().flatten().filter(|&&ch| ch == symbol).count() < 2
+        })
+    }
+
+    fn level(&self) -> Level {
+        Level(
+            self.grid
+                .iter()
+                .map(|row| {
+                    let line: String = row.iter().collect();
+                    line.trim_end().chars().collect()
+                })
+                .collect(),
+        )
+    }
+
+    // Tags on cells that were painted over or cut off are dropped
+    fn meta(&self) -> LevelMeta {
+        let level = self.level();
+        let switches = Switches::new(&level.0, &self.meta);
+        let is_duck = |(row, col): (usize, usize)| {
+            level.0
+                .get(row)
+                .and_then(|cells| cells.get(col))
+                .and_then(|&symbol| SymbolType::from_symbol(symbol))
+                .is_some_and(SymbolType::is_duck)
+        };
+        LevelMeta {
+            par: self.meta.par,
+            channels: self
+                .meta
+                .channels
+                .iter()
+                .copied()
+                .filter(|(position, _)| switches.channel(*position).is_some())
+                .collect(),
+            owners: self.meta.owners.iter().copied().filter(|(position, _)| is_duck(*position)).collect(),
+        }
+    }
+
+    fn has_duck(&self) -> bool {
+        self.grid
+            .iter()
+            .flatten()
+            .any(|&symbol| SymbolType::from_symbol(symbol).is_some_and(SymbolType::is_duck))
+    }
+}
+
+/// 从编辑器进入的试玩，Esc 回到编辑器
+#[derive(Resource)]
+struct EditorTestPlay;
+
+#[derive(Component)]
+struct EditorEntity;
+
+#[derive(Component)]
+struct EditorCanvas;
+
+// 画棋盘用到的图片和角色
+#[derive(SystemParam)]
+struct EditorSprites<'w> {
+    image_assets: Res<'w, ImageAssets>,
+    selected_characters: Res<'w, SelectedCharacters>,
+    characters: Res<'w, Characters>,
+}
+
+#[derive(Component)]
+struct EditorStatusText;
+
+#[derive(Component, Clone, Copy)]
+struct PaletteButton(usize);
+
+#[derive(Component, Clone, Copy)]
+enum EditorButton {
+    RemoveColumn,
+    AddColumn,
+    RemoveRow,
+    AddRow,
+    TestPlay,
+    Save,
+    Back,
+}
+
+// Start from the current level the first time, keep the board after a test play
+fn start_editing(
+    mut commands: Commands,
+    mut editor: ResMut<EditorLevel>,
+    mut custom_level: ResMut<CustomLevel>,
+    levels: Res<Levels>,
+    level_index: Res<CurrentLevelIndex>,
+) {
+    commands.remove_resource::<EditorTestPlay>();
+    custom_level.0 = None;
+    if editor.grid.is_empty() {
+        editor.meta = levels.meta(level_index.0);
+        let level = load_level(level_index.0, levels).unwrap_or_default();
+        editor.load(&level);
+    }
+    editor.status.clear();
+    // Draw the board
+    editor.set_changed();
+}
+
+fn setup_editor_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/NotJamChunky8.ttf");
+
+    // Palette on the left
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: Val::Px(10.0),
+                    top: Val::Px(90.0),
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(6.0),
+                    ..default()
+                },
+                ..default()
+            },
+            EditorEntity,
+        ))
+        .with_children(|parent| {
+            for (index, (label, _)) in PALETTE.iter().enumerate() {
+                let label = match PALETTE_KEYS.get(index) {
+                    Some((_, key)) => format!("{} {}", key, label),
+                    None => label.to_string(),
+                };
+                spawn_editor_button(parent, &font, &label, PaletteButton(index), 130.0, 32.0);
+            }
+        });
+
+    // Actions along the top
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    column_gap: Val::Px(8.0),
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            EditorEntity,
+        ))
+        .with_children(|parent| {
+            for (label, button, width) in [
+                ("Cols-", EditorButton::RemoveColumn, 100.0),
+                ("Cols+", EditorButton::AddColumn, 100.0),
+                ("Rows-", EditorButton::RemoveRow, 100.0),
+                ("Rows+", EditorButton::AddRow, 100.0),
+                ("Play", EditorButton::TestPlay, 100.0),
+                ("Save", EditorButton::Save, 100.0),
+                ("Back", EditorButton::Back, 100.0),
+            ] {
+                spawn_editor_button(parent, &font, label, button, width, 40.0);
+            }
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 16.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                EditorStatusText,
+            ));
+        });
+}
+
+fn spawn_editor_button(
+    parent: &mut ChildBuilder,
+    font: &Handle<Font>,
+    label: &str,
+    button: impl Component,
+    width: f32,
+    height: f32,
+) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(width),
+                    height: Val::Px(height),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: NORMAL_BUTTON.into(),
+                ..default()
+            },
+            button,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                label,
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                },
+            ));
+        });
+}
+
+fn editor_shortcuts(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut editor: ResMut<EditorLevel>,
+    mut next_state: ResMut<NextState<GameStates>>,
+) {
+    for (index, (key, _)) in PALETTE_KEYS.iter().enumerate().take(PALETTE.len()) {
+        if keyboard.just_pressed(*key) {
+            editor.pick_brush(index);
+        }
+    }
+    if keyboard.just_pressed(KeyCode::Escape) {
+        next_state.set(GameStates::GameMenu);
+    }
+}
+
+fn palette_interaction(
+    mut interaction_query: Query<(&Interaction, &PaletteButton), Changed<Interaction>>,
+    mut editor: ResMut<EditorLevel>,
+) {
+    for (interaction, PaletteButton(index)) in &mut interaction_query {
+        if *interaction == Interaction::Pressed {
+            editor.pick_brush(*index);
+        }
+    }
+}
+
+fn editor_button_interaction(
+    mut commands: Commands,
+    mut interaction_query: Query<
+        (&Interaction, &mut BackgroundColor, &EditorButton),
+        Changed<Interaction>,
+    >,
+    mut editor: ResMut<EditorLevel>,
+    mut custom_level: ResMut<CustomLevel>,
+    mut custom_level_meta: ResMut<CustomLevelMeta>,
+    mut level_index: ResMut<CurrentLevelIndex>,
+    mut play_mode: ResMut<PlayMode>,
+    mut next_state: ResMut<NextState<GameStates>>,
+) {
+    for (interaction, mut color, button) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *color = PRESSED_BUTTON.into();
+                let (rows, columns) = (editor.rows(), editor.columns());
+                match button {
+                    EditorButton::RemoveColumn => editor.resize(rows, columns.saturating_sub(1)),
+                    EditorButton::AddColumn => editor.resize(rows, columns + 1),
+                    EditorButton::RemoveRow => editor.resize(rows.saturating_sub(1), columns),
+                    EditorButton::AddRow => editor.resize(rows + 1, columns),
+                    EditorButton::TestPlay => {
+                        if !editor.has_duck() {
+                            editor.status = "Add a duck first".to_string();
+                            continue;
+                        }
+                        // 一个人试玩，控制所有的鸭子
+                        custom_level.0 = Some(editor.level());
+                        custom_level_meta.0 = editor.meta();
+                        *play_mode = PlayMode::Solo;
+                        commands.remove_resource::<AiOpponent>();
+                        commands.insert_resource(EditorTestPlay);
+                        // load_other_level spawns the board when the index changes
+                        level_index.set_changed();
+                        next_state.set(GameStates::Next);
+                    }
+                    EditorButton::Save => {
+                        editor.status = match save_level(&editor.level(), &editor.meta()) {
+                            Ok(file_name) => format!("Saved {}, add it to Levels to play", file_name),
+                            Err(e) => {
+                                warn!("Failed to save level: {}", e);
+                                "Save failed".to_string()
+                            }
+                        };
+                    }
+                    EditorButton::Back => next_state.set(GameStates::GameMenu),
+                }
+            }
+            Interaction::Hovered => {
+                *color = HOVERED_BUTTON.into();
+            }
+            Interaction::None => {
+                *color = NORMAL_BUTTON.into();
+            }
+        }
+    }
+}
+
+// Written next to the built-in levels with the next free number. It still has
+// to be added to `Levels` to show up in the game.
+fn save_level(level: &Level, meta: &LevelMeta) -> anyhow::Result<String> {
+    let dir = Path::new(LEVEL_DIR);
+    fs::create_dir_all(dir)?;
+    let file_name = (1..)
+        .map(|index| format!("level{}.txt", index))
+        .find(|file_name| !dir.join(file_name).exists())
+        .unwrap();
+    let content: Vec<String> = meta
+        .lines()
+        .into_iter()
+        .chain(level.0.iter().map(|row| row.iter().collect()))
+        .collect();
+    // Same line endings as the level files in the repo
+    fs::write(dir.join(&file_name), content.join("\r\n"))?;
+    Ok(file_name)
+}
+
+// Left button paints with the brush, right button erases. Nothing is painted
+// through the editor buttons.
+fn paint_tiles(
+    mouse: Res<ButtonInput<MouseButton>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    ui_query: Query<&Interaction>,
+    mut editor: ResMut<EditorLevel>,
+) {
+    let symbol = if mouse.pressed(MouseButton::Left) {
+        editor.brush_symbol()
+    } else if mouse.pressed(MouseButton::Right) {
+        Some(EMPTY)
+    } else {
+        None
+    };
+    let Some(symbol) = symbol else {
+        return;
+    };
+    if ui_query.iter().any(|interaction| *interaction != Interaction::None) {
+        return;
+    }
+    let (camera, camera_transform) = camera_query.single();
+    let Some(cursor) = window_query
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position())
+        .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor))
+    else {
+        return;
+    };
+    let Some((row, column)) = translation_to_logic_position(cursor) else {
+        return;
+    };
+    if row >= editor.rows() || column >= editor.columns() || editor.grid[row][column] == symbol {
+        return;
+    }
+    // Dragging a portal over a portal would break up its pair
+    let is_portal = |ch| matches!(SymbolType::from_symbol(ch), Some(SymbolType::Portal(_)));
+    if is_portal(symbol) && is_portal(editor.grid[row][column]) {
+        return;
+    }
+    editor.grid[row][column] = symbol;
+}
+
+// The whole board is spawned again after every change, like update_level does.
+// No Won here, a board without bread is not a cleared level
+fn redraw_level(
+    mut commands: Commands,
+    editor: Res<EditorLevel>,
+    sprites: EditorSprites,
+    level_index: Res<CurrentLevelIndex>,
+    mut bread_count: ResMut<BreadCount>,
+    query: Query<Entity, Or<(With<level::Object>, With<EditorCanvas>)>>,
+) {
+    if !editor.is_changed() {
+        return;
+    }
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    // Empty cells are painted on a slightly lighter background
+    let size = Vec2::new(editor.columns() as f32, editor.rows() as f32) * SPRITE_SIZE;
+    let first = logic_position_to_translation((0, 0));
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: CANVAS_COLOR,
+                custom_size: Some(size),
+                ..default()
+            },
+            transform: Transform::from_xyz(
+                first.x - SPRITE_SIZE / 2.0 + size.x / 2.0,
+                first.y + SPRITE_SIZE / 2.0 - size.y / 2.0,
+                -1.0,
+            ),
+            ..default()
+        },
+        EditorCanvas,
+    ));
+
+    let record = initial_record(
+        &editor.grid,
+        &editor.meta().owners,
+        PlayMode::Solo,
+        &sprites.selected_characters,
+        &sprites.characters,
+    );
+    spawn_sprites(
+        &mut commands,
+        &editor.grid,
+        &sprites.image_assets,
+        level_index.0,
+        &mut bread_count,
+        true,
+        &sprites.selected_characters,
+        &sprites.characters,
+        PlayMode::Solo,
+        &record,
+    );
+}
+
+fn update_editor_ui(
+    editor: Res<EditorLevel>,
+    mut palette_query: Query<(&PaletteButton, &mut BackgroundColor, &Interaction)>,
+    mut status_query: Query<&mut Text, With<EditorStatusText>>,
+) {
+    for (PaletteButton(index), mut color, interaction) in &mut palette_query {
+        *color = match (*index == editor.brush, interaction) {
+            (true, _) => SELECTED_BRUSH.into(),
+            (false, Interaction::Pressed) => PRESSED_BUTTON.into(),
+            (false, Interaction::Hovered) => HOVERED_BUTTON.into(),
+            (false, Interaction::None) => NORMAL_BUTTON.into(),
+        };
+    }
+    if !editor.is_changed() {
+        return;
+    }
+    for mut text in status_query.iter_mut() {
+        text.sections[0].value = format!(
+            "{}x{}  {}  {}",
+            editor.columns(),
+            editor.rows(),
+            editor.brush_label(),
+            editor.status
+        );
+    }
+}
+
+fn leave_test_play(keyboard: Res<ButtonInput<KeyCode>>, mut next_state: ResMut<NextState<GameStates>>) {
+    if keyboard.just_pressed(KeyCode::Escape) {
+        next_state.set(GameStates::Editor);
+    }
+}
+
+fn cleanup_editor(
+    mut commands: Commands,
+    query: Query<Entity, Or<(With<EditorEntity>, With<EditorCanvas>, With<level::Object>)>>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::BTreeMap;
+
+use renet::{RenetClient, RenetServer};
+
+use super::{
+    level::{custom_level_loaded, CurrentLevelIndex, LevelCleared, Levels},
+    profile::Profile,
+    progress::MoveCounter,
+    *,
+};
+use crate::networking::{race::RaceClock, ClientMessage, InboundServerMessage, ServerLink, ServerMessage};
+
+// Per-level high scores. The client keeps its own copy, the server keeps
+// the authoritative one for networked games and sends it to the clients.
+
+pub struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LocalLeaderboard>()
+            .init_resource::<ServerLeaderboard>()
+            .init_resource::<ViewedLevel>()
+            .add_systems(Startup, load_local_leaderboard)
+            .add_systems(Update, receive_leaderboard_updates)
+            .add_systems(
+                Update,
+                (
+                    record_local_score
+                        .run_if(in_state(GameStates::Next))
+                        .run_if(not(resource_exists::<RenetServer>))
+                        .run_if(not(resource_equals(PlayMode::Replay)))
+                        .run_if(not(custom_level_loaded)),
+                    update_leaderboard_popup,
+                ),
+            )
+            .add_systems(OnEnter(GameStates::Leaderboard), setup_leaderboard_screen)
+            .add_systems(
+                Update,
+                (
+                    leaderboard_input,
+                    leaderboard_button_interaction,
+                    request_server_leaderboard,
+                    update_leaderboard_screen,
+                )
+                    .chain()
+                    .run_if(in_state(GameStates::Leaderboard)),
+            )
+            .add_systems(OnExit(GameStates::Leaderboard), cleanup_leaderboard_screen);
+    }
+}
+
+const LOCAL_LEADERBOARD_FILE: &str = "leaderboard.ron";
+pub const SERVER_LEADERBOARD_FILE: &str = "server_leaderboard.ron";
+const MAX_ENTRIES: usize = 10;
+const POPUP_ENTRIES: usize = 5;
+const POPUP_SECS: f32 = 5.0;
+
+const NORMAL_BUTTON: Color = MY_ORANGE;
+const HOVERED_BUTTON: Color =
+    Color::srgb(222.0 / 255.0 + 0.1, 112.0 / 255.0 + 0.1, 40.0 / 255.0 + 0.1);
+const PRESSED_BUTTON: Color = Color::srgb(0.75, 0.75, 0.75);
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Score {
+    pub name: String,
+    pub moves: u32,
+    /// Only known for race games
+    pub seconds: Option<f32>,
+}
+
+impl Score {
+    fn describe(&self) -> String {
+        match self.seconds {
+            Some(seconds) => format!("{}  {} moves  {:.1}s", self.name, self.moves, seconds),
+            None => format!("{}  {} moves", self.name, self.moves),
+        }
+    }
+}
+
+// Fewer moves first, then the faster time
+#[derive(Default, Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Leaderboard {
+    pub levels: BTreeMap<usize, Vec<Score>>,
+}
+
+impl Leaderboard {
+    pub fn submit(&mut self, level_index: usize, score: Score) {
+        let scores = self.levels.entry(level_index).or_default();
+        scores.push(score);
+        scores.sort_by(|a, b| {
+            a.moves.cmp(&b.moves).then_with(|| {
+                let a = a.seconds.unwrap_or(f32::MAX);
+                let b = b.seconds.unwrap_or(f32::MAX);
+                a.total_cmp(&b)
+            })
+        });
+        scores.truncate(MAX_ENTRIES);
+    }
+
+    pub fn top(&self, level_index: usize) -> &[Score] {
+        self.levels.get(&level_index).map_or(&[], |scores| scores.as_slice())
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct LocalLeaderboard(pub Leaderboard);
+
+// On the server: the authoritative board. On a client: what the server sent us.
+#[derive(Resource, Default)]
+pub struct ServerLeaderboard(pub Leaderboard);
+
+/// Seconds on the race clock, if a race is running or just finished
+pub fn race_seconds(race_clock: &RaceClock) -> Option<f32> {
+    match race_clock {
+        RaceClock::Running(clock) => Some(clock.elapsed_secs()),
+        RaceClock::Over { elapsed, .. } => Some(*elapsed),
+        _ => None,
+    }
+}
+
+fn load_local_leaderboard(mut leaderboard: ResMut<LocalLeaderboard>) {
+    if let Some(saved) = storage::load::<Leaderboard>(LOCAL_LEADERBOARD_FILE) {
+        leaderboard.0 = saved;
+    }
+}
+
+fn receive_leaderboard_updates(
+    mut inbound: EventReader<InboundServerMessage>,
+    mut server_leaderboard: ResMut<ServerLeaderboard>,
+) {
+    for InboundServerMessage(message) in inbound.read() {
+        if let ServerMessage::LeaderboardUpdate { level_index, scores } = message {
+            server_leaderboard.0.levels.insert(*level_index, scores.clone());
+        }
+    }
+}
+
+#[derive(Component)]
+struct LeaderboardPopup(Timer);
+
+fn record_local_score(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut events: EventReader<LevelCleared>,
+    level_index: Res<CurrentLevelIndex>,
+    move_counter: Res<MoveCounter>,
+    race_clock: Res<RaceClock>,
+    profile: Res<Profile>,
+    mut leaderboard: ResMut<LocalLeaderboard>,
+    popup_query: Query<Entity, With<LeaderboardPopup>>,
+) {
+    for _ in events.read() {
+        leaderboard.0.submit(
+            level_index.0,
+            Score {
+                name: profile.player_name(),
+                moves: move_counter.total(),
+                seconds: race_seconds(&race_clock),
+            },
+        );
+        if let Err(e) = storage::save(LOCAL_LEADERBOARD_FILE, &leaderboard.0) {
+            warn!("Failed to save leaderboard: {}", e);
+        }
+
+        // Show the top scores for a few seconds
+        for entity in popup_query.iter() {
+            commands.entity(entity).despawn();
+        }
+        commands.spawn((
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/NotJamChunky8.ttf"),
+                    font_size: 16.0,
+                    ..default()
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(150.0),
+                right: Val::Px(10.0),
+                ..default()
+            }),
+            LeaderboardPopup(Timer::from_seconds(POPUP_SECS, TimerMode::Once)),
+        ));
+    }
+}
+
+// Prefer the server's board while connected, it is the shared one
+fn update_leaderboard_popup(
+    mut commands: Commands,
+    time: Res<Time>,
+    level_index: Res<CurrentLevelIndex>,
+    local: Res<LocalLeaderboard>,
+    server: Res<ServerLeaderboard>,
+    client: Option<Res<RenetClient>>,
+    mut query: Query<(Entity, &mut LeaderboardPopup, &mut Text)>,
+) {
+    let online = client.is_some_and(|client| client.is_connected());
+    let board = if online { &server.0 } else { &local.0 };
+    for (entity, mut popup, mut text) in query.iter_mut() {
+        if popup.0.tick(time.delta()).finished() || level_index.is_changed() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let mut value = String::from("Top scores");
+        for (rank, score) in board.top(level_index.0).iter().take(POPUP_ENTRIES).enumerate() {
+            value.push_str(&format!("\n{}. {}", rank + 1, score.describe()));
+        }
+        text.sections[0].value = value;
+    }
+}
+
+// The level shown on the leaderboard screen
+#[derive(Resource)]
+struct ViewedLevel(usize);
+
+impl Default for ViewedLevel {
+    fn default() -> Self {
+        ViewedLevel(1)
+    }
+}
+
+#[derive(Component)]
+struct LeaderboardEntity;
+
+#[derive(Component)]
+struct LevelLabel;
+
+#[derive(Component)]
+struct LocalScoresText;
+
+#[derive(Component)]
+struct ServerScoresText;
+
+#[derive(Component, Clone, Copy)]
+enum LeaderboardButton {
+    Previous,
+    Next,
+    Back,
+}
+
+fn setup_leaderboard_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut viewed_level: ResMut<ViewedLevel>,
+) {
+    // Make sure the server board is requested again
+    viewed_level.set_changed();
+    let font = asset_server.load("fonts/NotJamChunky8.ttf");
+    let text_style = |font_size: f32, color: Color| TextStyle {
+        font: font.clone(),
+        font_size,
+        color,
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(20.0),
+                    padding: UiRect::top(Val::Px(60.0)),
+                    ..default()
+                },
+                ..default()
+            },
+            LeaderboardEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section("LEADERBOARDS", text_style(60.0, MY_ORANGE)));
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(20.0),
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    spawn_leaderboard_button(parent, &font, "<", LeaderboardButton::Previous);
+                    parent.spawn((
+                        TextBundle::from_section("", text_style(30.0, Color::WHITE)),
+                        LevelLabel,
+                    ));
+                    spawn_leaderboard_button(parent, &font, ">", LeaderboardButton::Next);
+                });
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(80.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((
+                        TextBundle::from_section("", text_style(18.0, Color::WHITE)),
+                        LocalScoresText,
+                    ));
+                    parent.spawn((
+                        TextBundle::from_section("", text_style(18.0, Color::WHITE)),
+                        ServerScoresText,
+                    ));
+                });
+
+            spawn_leaderboard_button(parent, &font, "Back", LeaderboardButton::Back);
+        });
+}
+
+fn spawn_leaderboard_button(
+    parent: &mut ChildBuilder,
+    font: &Handle<Font>,
+    label: &str,
+    button: LeaderboardButton,
+) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(160.0),
+                    height: Val::Px(60.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: NORMAL_BUTTON.into(),
+                ..default()
+            },
+            button,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                label,
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 24.0,
+                    color: Color::WHITE,
+                },
+            ));
+        });
+}
+
+fn step_level(viewed_level: &mut ViewedLevel, levels: &Levels, forward: bool) {
+    let count = levels.levels.len();
+    viewed_level.0 = if forward {
+        viewed_level.0 % count + 1
+    } else {
+        (viewed_level.0 + count - 2) % count + 1
+    };
+}
+
+fn leaderboard_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    levels: Res<Levels>,
+    mut viewed_level: ResMut<ViewedLevel>,
+    mut next_state: ResMut<NextState<GameStates>>,
+) {
+    if keyboard.just_pressed(KeyCode::ArrowLeft) {
+        step_level(&mut viewed_level, &levels, false);
+    }
+    if keyboard.just_pressed(KeyCode::ArrowRight) {
+        step_level(&mut viewed_level, &levels, true);
+    }
+    if keyboard.just_pressed(KeyCode::Escape) {
+        next_state.set(GameStates::GameMenu);
+    }
+}
+
+fn leaderboard_button_interaction(
+    mut interaction_query: Query<
+        (&Interaction, &mut BackgroundColor, &LeaderboardButton),
+        Changed<Interaction>,
+    >,
+    levels: Res<Levels>,
+    mut viewed_level: ResMut<ViewedLevel>,
+    mut next_state: ResMut<NextState<GameStates>>,
+) {
+    for (interaction, mut color, button) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *color = PRESSED_BUTTON.into();
+                match button {
+                    LeaderboardButton::Previous => step_level(&mut viewed_level, &levels, false),
+                    LeaderboardButton::Next => step_level(&mut viewed_level, &levels, true),
+                    LeaderboardButton::Back => next_state.set(GameStates::GameMenu),
+                }
+            }
+            Interaction::Hovered => {
+                *color = HOVERED_BUTTON.into();
+            }
+            Interaction::None => {
+                *color = NORMAL_BUTTON.into();
+            }
+        }
+    }
+}
+
+// Ask the server for its board whenever another level is shown
+fn request_server_leaderboard(
+    viewed_level: Res<ViewedLevel>,
+    play_mode: Res<PlayMode>,
+    client: Option<Res<RenetClient>>,
+    mut link: ServerLink,
+) {
+    let online = client.is_some_and(|client| client.is_connected());
+    if online && *play_mode == PlayMode::Online && viewed_level.is_changed() {
+        link.send(ClientMessage::RequestLeaderboard(viewed_level.0));
+    }
+}
+
+fn update_leaderboard_screen(
+    viewed_level: Res<ViewedLevel>,
+    local: Res<LocalLeaderboard>,
+    server: Res<ServerLeaderboard>,
+    client: Option<Res<RenetClient>>,
+    mut label_query: Query<&mut Text, (With<LevelLabel>, Without<LocalScoresText>, Without<ServerScoresText>)>,
+    mut local_query: Query<&mut Text, (With<LocalScoresText>, Without<LevelLabel>, Without<ServerScoresText>)>,
+    mut server_query: Query<&mut Text, (With<ServerScoresText>, Without<LevelLabel>, Without<LocalScoresText>)>,
+) {
+    let list = |title: &str, scores: &[Score]| {
+        let mut value = title.to_string();
+        if scores.is_empty() {
+            value.push_str("\nNo scores yet");
+        }
+        for (rank, score) in scores.iter().enumerate() {
+            value.push_str(&format!("\n{}. {}", rank + 1, score.describe()));
+        }
+        value
+    };
+    for mut text in label_query.iter_mut() {
+        text.sections[0].value = format!("Level{}", viewed_level.0);
+    }
+    for mut text in local_query.iter_mut() {
+        text.sections[0].value = list("This computer", local.0.top(viewed_level.0));
+    }
+    let online = client.is_some_and(|client| client.is_connected());
+    for mut text in server_query.iter_mut() {
+        text.sections[0].value = if online {
+            list("Server", server.0.top(viewed_level.0))
+        } else {
+            "Server\nNot connected".to_string()
+        };
+    }
+}
+
+fn cleanup_leaderboard_screen(mut commands: Commands, query: Query<Entity, With<LeaderboardEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
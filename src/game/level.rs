@@ -1,6 +1,15 @@
-use super::{cursor::ArrowHint, player::CommonDuck, ui::Won, *};
+use super::{
+    character::Characters,
+    cursor::{ArrowHint, CharacterTag},
+    player::CommonDuck,
+    progress::MoveCounter,
+    ui::Won,
+    *,
+};
+use bevy::ecs::system::SystemParam;
+use bevy::utils::{Duration, HashMap};
 use thiserror::Error;
-use crate::game::player::{Player1,Player2};
+use crate::game::player::{Player1,Player2,NEUTRAL};
 
 #[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
 pub struct GameLoop;
@@ -17,11 +26,20 @@ impl bevy::app::Plugin for Plugin {
             .init_resource::<TotalBreadCount>()
             .init_resource::<LevelStack>()
             .init_resource::<BreadSumRecordStack>()
+            .init_resource::<MoveCountStack>()
+            .init_resource::<RedoStack>()
+            .init_resource::<CustomLevel>()
+            .init_resource::<CustomLevelMeta>()
+            .init_resource::<Switches>()
+            .init_resource::<GateStates>()
             .add_event::<PrintLevel>()
             .add_event::<UpdateLevel>()
             .add_event::<RestartLevelEvent>()
             .add_event::<UndoLevelEvent>()
             .add_event::<ChangeLevelEvent>()
+            .add_event::<LevelStarted>()
+            .add_event::<LevelCleared>()
+            .init_resource::<ClearedLatch>()
             .add_systems(
                 Update,
                 (
@@ -32,6 +50,9 @@ impl bevy::app::Plugin for Plugin {
                     change_level_cheats,
                     undo_the_level,
                     handle_completion,
+                    latch_level_cleared,
+                    build_switches,
+                    paint_switches,
                 )
                     .run_if(in_state(GameStates::Next)),
             );
@@ -52,34 +73,10 @@ macro_rules! load_levels {
 
 // IMPORTANT: Remember to add corresponding level file path
 // wasm version can't use std library
+// "/" works in include_str! on every platform, so there is only one list
 // no "," in the last file path
 impl Default for Levels {
     fn default() -> Self {
-        #[cfg(target_os = "windows")]
-        let levels = load_levels!(
-            "..\\..\\assets\\levels\\level1.txt",
-            "..\\..\\assets\\levels\\level2.txt",
-            "..\\..\\assets\\levels\\level3.txt",
-            "..\\..\\assets\\levels\\level4.txt",
-            "..\\..\\assets\\levels\\level5.txt",
-            "..\\..\\assets\\levels\\level6.txt",
-            "..\\..\\assets\\levels\\level7.txt",
-            "..\\..\\assets\\levels\\level8.txt",
-            "..\\..\\assets\\levels\\level9.txt",
-            "..\\..\\assets\\levels\\level10.txt",
-            "..\\..\\assets\\levels\\level11.txt",
-            "..\\..\\assets\\levels\\level12.txt",
-            "..\\..\\assets\\levels\\level13.txt",
-            "..\\..\\assets\\levels\\level14.txt",
-            "..\\..\\assets\\levels\\level15.txt",
-            "..\\..\\assets\\levels\\level16.txt",
-            "..\\..\\assets\\levels\\level17.txt",
-            "..\\..\\assets\\levels\\level18.txt",
-            "..\\..\\assets\\levels\\level19.txt",
-            "..\\..\\assets\\levels\\level20.txt"
-        );
-
-        #[cfg(any(target_os = "linux", target_os = "macos", target_arch = "wasm32"))]
         let levels = load_levels!(
             "../../assets/levels/level1.txt",
             "../../assets/levels/level2.txt",
@@ -93,13 +90,90 @@ impl Default for Levels {
             "../../assets/levels/level10.txt",
             "../../assets/levels/level11.txt",
             "../../assets/levels/level12.txt",
-            "../../assets/levels/level13.txt"
+            "../../assets/levels/level13.txt",
+            "../../assets/levels/level14.txt",
+            "../../assets/levels/level15.txt",
+            "../../assets/levels/level16.txt",
+            "../../assets/levels/level17.txt",
+            "../../assets/levels/level18.txt",
+            "../../assets/levels/level19.txt",
+            "../../assets/levels/level20.txt",
+            "../../assets/levels/level21.txt",
+            "../../assets/levels/level22.txt",
+            "../../assets/levels/level23.txt",
+            "../../assets/levels/level24.txt",
+            "../../assets/levels/level25.txt",
+            "../../assets/levels/level26.txt",
+            "../../assets/levels/level27.txt"
         );
 
         Self { levels }
     }
 }
 
+// 关卡文件开头以 ';' 开头的行是元数据，比如 ";par 8"
+const META_PREFIX: char = ';';
+
+/// 关卡元数据
+#[derive(Default, Clone, Debug)]
+pub struct LevelMeta {
+    /// 通关的标准步数
+    pub par: Option<u32>,
+    /// 压力板和闸门所在的频道，比如 ";channel 3 5 1"（行、列从 0 开始），没写的在频道 0
+    pub channels: Vec<((usize, usize), u8)>,
+    /// 鸭子的主人，比如 ";duck 2 4 1"，0 表示没有主人
+    pub owners: Vec<((usize, usize), u8)>,
+}
+
+impl Levels {
+    pub fn meta(&self, level_index: usize) -> LevelMeta {
+        level_index
+            .checked_sub(1)
+            .and_then(|i| self.levels.get(i))
+            .map_or_else(LevelMeta::default, |content| LevelMeta::parse(content))
+    }
+}
+
+impl LevelMeta {
+    /// 读出关卡文件里的元数据行，其他行忽略
+    pub fn parse(content: &str) -> Self {
+        let mut meta = LevelMeta::default();
+        for line in content.lines().filter_map(|line| line.strip_prefix(META_PREFIX)) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["par", value, ..] => meta.par = value.parse().ok(),
+                ["channel", row, col, channel, ..] => {
+                    if let (Ok(row), Ok(col), Ok(channel)) = (row.parse(), col.parse(), channel.parse()) {
+                        meta.channels.push(((row, col), channel));
+                    }
+                }
+                ["duck", row, col, owner, ..] => {
+                    if let (Ok(row), Ok(col), Ok(owner)) = (row.parse(), col.parse(), owner.parse()) {
+                        meta.owners.push(((row, col), owner));
+                    }
+                }
+                _ => {}
+            }
+        }
+        meta
+    }
+
+    /// 写回关卡文件的元数据行，和 parse 对应
+    pub fn lines(&self) -> Vec<String> {
+        let tagged = |name: &str, tags: &[((usize, usize), u8)]| -> Vec<String> {
+            tags.iter()
+                .map(|((row, col), value)| format!("{}{} {} {} {}", META_PREFIX, name, row, col, value))
+                .collect()
+        };
+        self.par
+            .map(|par| format!("{}par {}", META_PREFIX, par))
+            .into_iter()
+            .chain(tagged("channel", &self.channels))
+            .chain(tagged("duck", &self.owners))
+            .collect()
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum GameError {
     #[error("Fail to load level!")]
@@ -110,16 +184,88 @@ pub fn load_level(level_index: usize, levels: Res<Levels>) -> anyhow::Result<Lev
     levels
         .levels
         .get(level_index - 1)
-        .map(|&level_content| {
-            let level_data: Vec<Vec<char>> = level_content
-                .lines()
-                .map(|line| line.chars().collect())
-                .collect();
-            Level(level_data)
-        })
+        .map(|&level_content| parse_level(level_content))
         .ok_or_else(|| GameError::FailToLoadLevels.into())
 }
 
+/// 关卡文件里除了元数据以外的行就是棋盘
+pub fn parse_level(content: &str) -> Level {
+    Level(
+        content
+            .lines()
+            .filter(|line| !line.starts_with(META_PREFIX))
+            .map(|line| line.chars().collect())
+            .collect(),
+    )
+}
+
+/// 不在 Levels 里的关卡，比如录像里保存的棋盘。为 Some 时代替当前关卡文件
+#[derive(Resource, Default)]
+pub struct CustomLevel(pub Option<Level>);
+
+/// 编辑器试玩的关卡的元数据，录像还是用关卡文件里的
+#[derive(Resource, Default)]
+pub struct CustomLevelMeta(pub LevelMeta);
+
+/// 运行条件：正在玩的不是关卡文件里的关卡（录像或者编辑器里试玩）
+pub fn custom_level_loaded(custom_level: Res<CustomLevel>) -> bool {
+    custom_level.0.is_some()
+}
+
+/// 关卡（重新）开始时发出，带着初始棋盘
+#[derive(Event, Clone)]
+pub struct LevelStarted {
+    pub level_index: usize,
+    pub level: Level,
+}
+
+/// 每次开始一关后第一次吃完面包时发出。撤销、重做后再吃完不会再发，
+/// 成绩和进度只按这个记录
+#[derive(Event, Default)]
+pub struct LevelCleared;
+
+// 这一关是否已经发过 LevelCleared
+#[derive(Resource, Default)]
+struct ClearedLatch(bool);
+
+// 取得当前要玩的棋盘，并通知关卡开始
+#[derive(SystemParam)]
+pub struct LevelLoader<'w> {
+    levels: Res<'w, Levels>,
+    level_index: Res<'w, CurrentLevelIndex>,
+    custom_level: Res<'w, CustomLevel>,
+    custom_level_meta: Res<'w, CustomLevelMeta>,
+    play_mode: Res<'w, PlayMode>,
+    started: EventWriter<'w, LevelStarted>,
+}
+
+impl LevelLoader<'_> {
+    pub fn level_index(&self) -> usize {
+        self.level_index.0
+    }
+
+    /// 和 build_switches 一样，编辑器试玩的关卡用编辑器里的元数据
+    fn meta(&self) -> LevelMeta {
+        if self.custom_level.0.is_none() || *self.play_mode == PlayMode::Replay {
+            self.levels.meta(self.level_index.0)
+        } else {
+            self.custom_level_meta.0.clone()
+        }
+    }
+
+    fn load(&mut self) -> Option<Level> {
+        let level = match &self.custom_level.0 {
+            Some(level) => level.clone(),
+            None => load_level(self.level_index.0, Res::clone(&self.levels)).ok()?,
+        };
+        self.started.send(LevelStarted {
+            level_index: self.level_index.0,
+            level: level.clone(),
+        });
+        Some(level)
+    }
+}
+
 #[derive(Resource)]
 pub struct LevelStack(pub Stack<Vec<Vec<char>>>);
 
@@ -129,14 +275,76 @@ impl Default for LevelStack {
     }
 }
 
+/// 撤销时要恢复的鸭子状态
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DuckRecord {
+    pub bread_count: u32,
+    /// 吃下去几个面包，和分数不一样
+    pub loaves: u32,
+    pub heavy: bool,
+    pub peppers: u32,
+    /// 这一关还能用几次能力
+    pub ability_uses: u32,
+    /// 属于哪个玩家，0 表示没有主人
+    pub owner: u8,
+    /// 是不是主人正在操作的那只
+    pub active: bool,
+}
+
+/// 每只鸭子的位置和肚子里的东西
+pub type BreadRecord = Vec<((usize, usize), DuckRecord)>;
+
+// 和 LevelStack 一一对应，撤销时恢复每只鸭子的面包数
 #[derive(Resource)]
-pub struct BreadSumRecordStack(pub Stack<Vec<((usize, usize), u32)>>);
+pub struct BreadSumRecordStack(pub Stack<BreadRecord>);
 impl Default for BreadSumRecordStack {
     fn default() -> Self {
         BreadSumRecordStack(Stack::new())
     }
 }
 
+// 和 LevelStack 一一对应，撤销时恢复步数
+#[derive(Resource)]
+pub struct MoveCountStack(pub Stack<MoveCounter>);
+impl Default for MoveCountStack {
+    fn default() -> Self {
+        MoveCountStack(Stack::new())
+    }
+}
+
+// 撤销和重做用到的栈，开始一关时一起清空
+#[derive(SystemParam)]
+pub struct UndoStacks<'w> {
+    pub levels: ResMut<'w, LevelStack>,
+    pub bread_records: ResMut<'w, BreadSumRecordStack>,
+    pub move_counts: ResMut<'w, MoveCountStack>,
+    pub redo: ResMut<'w, RedoStack>,
+}
+
+impl UndoStacks<'_> {
+    fn clear(&mut self) {
+        self.levels.0.clear();
+        self.bread_records.0.clear();
+        self.move_counts.0.clear();
+        self.redo.0.clear();
+    }
+
+    fn push(&mut self, level: &Level, record: BreadRecord, moves: MoveCounter) {
+        self.levels.0.push(level.0.clone());
+        self.bread_records.0.push(record);
+        self.move_counts.0.push(moves);
+    }
+}
+
+// 撤销掉的棋盘、面包记录和步数，新的移动会清空它
+#[derive(Resource)]
+pub struct RedoStack(pub Stack<(Vec<Vec<char>>, BreadRecord, MoveCounter)>);
+impl Default for RedoStack {
+    fn default() -> Self {
+        RedoStack(Stack::new())
+    }
+}
+
 #[derive(Resource, Default,Clone,Debug,serde::Serialize, serde::Deserialize)]
 pub struct Level(pub Vec<Vec<char>>);
 
@@ -160,6 +368,7 @@ impl Default for BreadCount {
     }
 }
 // TODO: Layers of objects (z axis)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum SymbolType {
     Wall,
     Ice,
@@ -170,10 +379,67 @@ pub enum SymbolType {
     BreakingIce,
     DuckOnWater,
     DuckOnBreakingIce,
+    // 单向箭头：滑进来的鸭子转向箭头的方向，不能逆着箭头进入
+    ArrowUp,
+    ArrowDown,
+    ArrowLeft,
+    ArrowRight,
+    // 传送门：同一个数字的两个格子互相连通
+    Portal(u8),
+    // 冰块：鸭子撞上去会把它推走，推进水里会把水填平
+    IceBlock,
+    // 压力板：有鸭子站着时同一频道的闸门打开
+    Plate,
+    // 开关板：鸭子停在上面时切换同一频道的闸门
+    TogglePlate,
+    ClosedGate,
+    OpenGate,
+    // 有裂纹的冰：每经过一次裂得更厉害，$ 变成 +，+ 变成 *
+    CrackedIce,
+    SturdyIce,
+    DuckOnCrackedIce,
+    // 停在要碎的冰上、还能走的鸭子。O 是旧的写法，留着读以前的录像
+    DuckOnThinIce,
+    // 金面包分数更高；吃了沉面包的鸭子会把身后的冰压裂；辣椒让鸭子吃掉下一个面包后继续滑
+    GoldenBread,
+    HeavyBread,
+    Pepper,
 }
 
 // Symbols
 impl SymbolType {
+    pub fn from_symbol(ch: char) -> Option<Self> {
+        match ch {
+            '@' => Some(SymbolType::Wall),
+            '#' => Some(SymbolType::Ice),
+            '^' => Some(SymbolType::BrokenIce),
+            'D' => Some(SymbolType::DuckOnIce),
+            'B' => Some(SymbolType::BreadOnIce),
+            '*' => Some(SymbolType::BreakingIce),
+            'P' => Some(SymbolType::DuckOnWater),
+            'O' => Some(SymbolType::DuckOnBreakingIce),
+            'Q' => Some(SymbolType::StuffedDuckOnIce),
+            'A' => Some(SymbolType::ArrowUp),
+            'v' => Some(SymbolType::ArrowDown),
+            '<' => Some(SymbolType::ArrowLeft),
+            '>' => Some(SymbolType::ArrowRight),
+            '1'..='9' => ch.to_digit(10).map(|pair| SymbolType::Portal(pair as u8)),
+            'K' => Some(SymbolType::IceBlock),
+            '_' => Some(SymbolType::Plate),
+            '%' => Some(SymbolType::TogglePlate),
+            '|' => Some(SymbolType::ClosedGate),
+            '=' => Some(SymbolType::OpenGate),
+            '+' => Some(SymbolType::CrackedIce),
+            '$' => Some(SymbolType::SturdyIce),
+            'C' => Some(SymbolType::DuckOnCrackedIce),
+            'T' => Some(SymbolType::DuckOnThinIce),
+            'G' => Some(SymbolType::GoldenBread),
+            'H' => Some(SymbolType::HeavyBread),
+            'R' => Some(SymbolType::Pepper),
+            _ => None,
+        }
+    }
+
     pub fn get_symbol(self) -> char {
         match self {
             SymbolType::Wall => '@',
@@ -185,8 +451,118 @@ impl SymbolType {
             SymbolType::BreakingIce => '*',
             SymbolType::DuckOnWater => 'P',
             SymbolType::DuckOnBreakingIce => 'O',
+            SymbolType::ArrowUp => 'A',
+            SymbolType::ArrowDown => 'v',
+            SymbolType::ArrowLeft => '<',
+            SymbolType::ArrowRight => '>',
+            SymbolType::Portal(pair) => char::from_digit(pair.into(), 10).unwrap_or('1'),
+            SymbolType::IceBlock => 'K',
+            SymbolType::Plate => '_',
+            SymbolType::TogglePlate => '%',
+            SymbolType::ClosedGate => '|',
+            SymbolType::OpenGate => '=',
+            SymbolType::CrackedIce => '+',
+            SymbolType::SturdyIce => '$',
+            SymbolType::DuckOnCrackedIce => 'C',
+            SymbolType::DuckOnThinIce => 'T',
+            SymbolType::GoldenBread => 'G',
+            SymbolType::HeavyBread => 'H',
+            SymbolType::Pepper => 'R',
         }
     }
+
+    pub fn is_duck(self) -> bool {
+        matches!(
+            self,
+            SymbolType::DuckOnIce
+                | SymbolType::StuffedDuckOnIce
+                | SymbolType::DuckOnWater
+                | SymbolType::DuckOnBreakingIce
+                | SymbolType::DuckOnCrackedIce
+                | SymbolType::DuckOnThinIce
+        )
+    }
+
+    pub fn is_bread(self) -> bool {
+        matches!(
+            self,
+            SymbolType::BreadOnIce | SymbolType::GoldenBread | SymbolType::HeavyBread
+        )
+    }
+
+    /// 吃掉这个面包得几分
+    pub fn bread_points(self) -> u32 {
+        match self {
+            SymbolType::GoldenBread => 3,
+            _ => 1,
+        }
+    }
+
+    /// 特殊面包用普通面包的贴图染色
+    pub fn bread_tint(self) -> Color {
+        match self {
+            SymbolType::GoldenBread => Color::srgb(1.0, 0.85, 0.2),
+            SymbolType::HeavyBread => Color::srgb(0.55, 0.45, 0.4),
+            _ => Color::WHITE,
+        }
+    }
+
+    /// 鸭子经过一次以后的样子
+    pub fn crack(self) -> Option<SymbolType> {
+        match self {
+            SymbolType::SturdyIce => Some(SymbolType::CrackedIce),
+            SymbolType::CrackedIce => Some(SymbolType::BreakingIce),
+            _ => None,
+        }
+    }
+
+    pub fn arrow_direction(self) -> Option<utils::Direction> {
+        match self {
+            SymbolType::ArrowUp => Some(utils::Direction::Up),
+            SymbolType::ArrowDown => Some(utils::Direction::Down),
+            SymbolType::ArrowLeft => Some(utils::Direction::Left),
+            SymbolType::ArrowRight => Some(utils::Direction::Right),
+            _ => None,
+        }
+    }
+}
+
+/// 传送门另一头的位置，没有配对时为 None
+pub fn portal_twin(level: &[Vec<char>], position: (usize, usize)) -> Option<(usize, usize)> {
+    let symbol = level[position.0][position.1];
+    level.iter().enumerate().find_map(|(row_index, row)| {
+        row.iter()
+            .enumerate()
+            .find(|&(col_index, &ch)| ch == symbol && (row_index, col_index) != position)
+            .map(|(col_index, _)| (row_index, col_index))
+    })
+}
+
+// 每一对传送门、每一个开关频道一个颜色
+const PAIR_COLORS: [Color; 9] = [
+    Color::srgb(0.4, 0.8, 1.0),
+    Color::srgb(1.0, 0.6, 0.2),
+    Color::srgb(0.7, 0.5, 1.0),
+    Color::srgb(0.4, 1.0, 0.5),
+    Color::srgb(1.0, 0.5, 0.7),
+    Color::srgb(1.0, 0.9, 0.3),
+    Color::srgb(0.3, 0.5, 1.0),
+    Color::srgb(1.0, 0.3, 0.3),
+    Color::srgb(0.6, 0.9, 0.9),
+];
+
+pub fn pair_color(id: u8) -> Color {
+    PAIR_COLORS[usize::from(id.saturating_sub(1)) % PAIR_COLORS.len()]
+}
+
+/// 箭头贴图朝右，按方向旋转
+pub fn arrow_rotation(direction: utils::Direction) -> Quat {
+    match direction {
+        utils::Direction::Up => Quat::from_rotation_z(std::f32::consts::FRAC_PI_2),
+        utils::Direction::Down => Quat::from_rotation_z(-std::f32::consts::FRAC_PI_2),
+        utils::Direction::Left => Quat::from_rotation_z(std::f32::consts::PI),
+        _ => Quat::IDENTITY,
+    }
 }
 
 #[derive(Component)]
@@ -210,33 +586,45 @@ fn spawn_level(
     mut commands: Commands,
     // resource
     image_assets: Res<ImageAssets>,
-    level_index: Res<CurrentLevelIndex>,
+    mut loader: LevelLoader,
     mut bread_count: ResMut<BreadCount>,
     mut total_bread_count: ResMut<TotalBreadCount>,
-    levels: Res<Levels>,
-    mut level_stack: ResMut<LevelStack>,
-    mut bread_sum_record_stack: ResMut<BreadSumRecordStack>,
+    mut undo_stacks: UndoStacks,
     // event
     mut events: EventWriter<Won>,
     selected_characters: Res<SelectedCharacters>,
+    characters: Res<Characters>,
+    play_mode: Res<PlayMode>,
+    mut move_counter: ResMut<MoveCounter>,
 ) {
     // Load the level from a .txt file
-    if let Ok(level) = load_level(level_index.0, levels) {
+    if let Some(level) = loader.load() {
         // clear the stack
-        level_stack.0.clear();
-        bread_sum_record_stack.0.clear();
+        undo_stacks.clear();
+        *move_counter = MoveCounter::default();
 
+        let record = initial_record(
+            &level.0,
+            &loader.meta().owners,
+            *play_mode,
+            &selected_characters,
+            &characters,
+        );
         spawn_sprites(
             &mut commands,
             &level.0,
             &image_assets,
-            level_index.0,
+            loader.level_index(),
             &mut bread_count,
-            &mut events,
             true,
             &selected_characters,
+            &characters,
+            *play_mode,
+            &record,
         );
-        level_stack.0.push(level.0.clone());
+        send_won_if_cleared(&bread_count, &mut events);
+        // 撤销回开头时也要恢复鸭子的主人
+        undo_stacks.push(&level, record, MoveCounter::default());
         commands.insert_resource(level);
         total_bread_count.0 = bread_count.0;
     }
@@ -249,14 +637,17 @@ pub fn update_level(
     mut events: EventWriter<Won>,
     // add the objects that won't be despawn to the filter
     object_query: Query<Entity, (With<Object>, Without<CommonDuck>, Without<ArrowHint>)>,
+    duck_query: Query<(&CommonDuck, Has<Player1>, Has<Player2>)>,
     // resource
     image_assets: Res<ImageAssets>,
     level: Res<Level>,
     level_index: Res<CurrentLevelIndex>,
     mut bread_count: ResMut<BreadCount>,
-    mut level_stack: ResMut<LevelStack>,
-    mut bread_sum_record_stack: ResMut<BreadSumRecordStack>,
+    mut undo_stacks: UndoStacks,
+    move_counter: Res<MoveCounter>,
     selected_characters: Res<SelectedCharacters>,
+    characters: Res<Characters>,
+    play_mode: Res<PlayMode>,
 ) {
     for _ in events_update.read() {
         // Do not despawn ducks, update the translations of ducks
@@ -265,19 +656,32 @@ pub fn update_level(
         for object in &object_query {
             commands.entity(object).despawn();
         }
-        level_stack.0.push(level.0.clone());
-        let record: Vec<((usize, usize), u32)> = vec![];
-        bread_sum_record_stack.0.push(record);
+        let record: BreadRecord = duck_query
+            .iter()
+            .map(|(duck, player1, player2)| (duck.logic_position, duck.record(player1 || player2)))
+            .collect();
+        undo_stacks.push(&level, record, *move_counter);
+        undo_stacks.redo.0.clear();
         spawn_sprites(
             &mut commands,
             &level.0,
             &image_assets,
             level_index.0,
             &mut bread_count,
-            &mut events,
             false,
             &selected_characters,
+            &characters,
+            *play_mode,
+            &[],
         );
+        send_won_if_cleared(&bread_count, &mut events);
+    }
+}
+
+// 编辑器画棋盘时也用 spawn_sprites，所以过关由调用的系统判断
+fn send_won_if_cleared(bread_count: &BreadCount, events: &mut EventWriter<Won>) {
+    if bread_count.0 == 0 {
+        events.send(Won);
     }
 }
 
@@ -312,6 +716,290 @@ pub fn spawn_upper_object(commands: &mut Commands, position: Vec3, sprite: Handl
     ));
 }
 
+pub fn spawn_tinted_upper_object(commands: &mut Commands, position: Vec3, sprite: Handle<Image>, color: Color) {
+    commands.spawn((
+        SpriteBundle {
+            texture: sprite,
+            sprite: Sprite { color, ..default() },
+            transform: Transform {
+                translation: Vec3::new(position.x, position.y, position.z + 1.0),
+                rotation: Quat::IDENTITY,
+                scale: Vec3::new(1.0 * RESIZE, 1.0 * RESIZE, 1.0),
+            },
+            ..default()
+        },
+        Object,
+    ));
+}
+
+/// 吃过沉面包的鸭子颜色深一些
+pub fn duck_tint(heavy: bool) -> Color {
+    if heavy {
+        Color::srgb(0.6, 0.6, 0.7)
+    } else {
+        Color::WHITE
+    }
+}
+
+/// 压力板和闸门的位置。鸭子站上去以后格子里只剩鸭子的符号，靠这里把地面恢复出来。
+/// 闸门开没开记在棋盘上，撤销时跟着棋盘一起恢复
+#[derive(Resource, Default, Clone, Debug)]
+pub struct Switches {
+    plates: Vec<Plate>,
+    gates: Vec<((usize, usize), u8)>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Plate {
+    position: (usize, usize),
+    channel: u8,
+    toggle: bool,
+}
+
+impl Plate {
+    fn symbol(&self) -> char {
+        if self.toggle {
+            SymbolType::TogglePlate.get_symbol()
+        } else {
+            SymbolType::Plate.get_symbol()
+        }
+    }
+}
+
+impl Switches {
+    pub fn new(level: &[Vec<char>], meta: &LevelMeta) -> Self {
+        let channel_of = |position: (usize, usize)| {
+            meta.channels
+                .iter()
+                .find(|(channel_position, _)| *channel_position == position)
+                .map_or(0, |(_, channel)| *channel)
+        };
+        let mut switches = Switches::default();
+        for (row_index, row) in level.iter().enumerate() {
+            for (col_index, &ch) in row.iter().enumerate() {
+                let position = (row_index, col_index);
+                match SymbolType::from_symbol(ch) {
+                    Some(SymbolType::Plate | SymbolType::TogglePlate) => switches.plates.push(Plate {
+                        position,
+                        channel: channel_of(position),
+                        toggle: ch == SymbolType::TogglePlate.get_symbol(),
+                    }),
+                    Some(SymbolType::ClosedGate | SymbolType::OpenGate) => {
+                        switches.gates.push((position, channel_of(position)))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        switches
+    }
+
+    pub fn channel(&self, position: (usize, usize)) -> Option<u8> {
+        self.plates
+            .iter()
+            .map(|plate| (plate.position, plate.channel))
+            .chain(self.gates.iter().copied())
+            .find(|(switch_position, _)| *switch_position == position)
+            .map(|(_, channel)| channel)
+    }
+
+    /// 一只鸭子从 from 滑到 to 以后重新计算闸门
+    pub fn update(&self, level: &mut Level, from: (usize, usize), to: (usize, usize)) {
+        let ice = SymbolType::Ice.get_symbol();
+        let open = SymbolType::OpenGate.get_symbol();
+        let closed = SymbolType::ClosedGate.get_symbol();
+
+        // 鸭子离开的格子被写成了冰面，恢复成压力板或闸门。站过鸭子的闸门一定是开着的
+        for plate in &self.plates {
+            let cell = &mut level.0[plate.position.0][plate.position.1];
+            if *cell == ice {
+                *cell = plate.symbol();
+            }
+        }
+        for ((row, col), _) in &self.gates {
+            if level.0[*row][*col] == ice {
+                level.0[*row][*col] = open;
+            }
+        }
+
+        // 停在开关板上切换闸门，有鸭子站着的闸门关不上
+        if from != to {
+            if let Some(plate) = self.plates.iter().find(|plate| plate.toggle && plate.position == to) {
+                for ((row, col), _) in self.gates.iter().filter(|(_, channel)| *channel == plate.channel) {
+                    let cell = &mut level.0[*row][*col];
+                    if *cell == open {
+                        *cell = closed;
+                    } else if *cell == closed {
+                        *cell = open;
+                    }
+                }
+            }
+        }
+
+        // 有压力板的频道：任何一块压力板上有鸭子时闸门打开，否则关上
+        for plate in self.plates.iter().filter(|plate| !plate.toggle) {
+            let pressed = self.plates.iter().any(|other| {
+                !other.toggle
+                    && other.channel == plate.channel
+                    && level.0[other.position.0][other.position.1] != other.symbol()
+            });
+            for ((row, col), _) in self.gates.iter().filter(|(_, channel)| *channel == plate.channel) {
+                let cell = &mut level.0[*row][*col];
+                if *cell == open || *cell == closed {
+                    *cell = if pressed { open } else { closed };
+                }
+            }
+        }
+    }
+}
+
+// 关卡开始时按初始棋盘记下开关的位置。编辑器试玩的关卡用编辑器里的频道
+fn build_switches(
+    mut started: EventReader<LevelStarted>,
+    levels: Res<Levels>,
+    custom_level: Res<CustomLevel>,
+    custom_level_meta: Res<CustomLevelMeta>,
+    play_mode: Res<PlayMode>,
+    mut switches: ResMut<Switches>,
+    mut gate_states: ResMut<GateStates>,
+) {
+    for LevelStarted { level_index, level } in started.read() {
+        let meta = if custom_level.0.is_none() || *play_mode == PlayMode::Replay {
+            levels.meta(*level_index)
+        } else {
+            custom_level_meta.0.clone()
+        };
+        *switches = Switches::new(&level.0, &meta);
+        gate_states.0.clear();
+    }
+}
+
+/// 压力板和闸门的贴图，按频道上色
+#[derive(Component)]
+pub struct SwitchSprite {
+    position: (usize, usize),
+    /// 闸门是否打开，压力板为 None
+    gate_open: Option<bool>,
+}
+
+/// 上一次画出来时每个闸门开没开，用来播放开关的动画
+#[derive(Resource, Default)]
+pub struct GateStates(pub HashMap<(usize, usize), bool>);
+
+fn gate_scale(open: bool) -> Vec3 {
+    // 打开的闸门缩到地面上，只留一个小标记
+    let size = if open { 0.3 * RESIZE } else { RESIZE };
+    Vec3::new(size, size, 1.0)
+}
+
+// update_level 每次都重新生成贴图，和上一次比较后让变化了的闸门从原来的样子动起来
+fn paint_switches(
+    mut commands: Commands,
+    switches: Res<Switches>,
+    mut gate_states: ResMut<GateStates>,
+    mut query: Query<(Entity, Ref<SwitchSprite>, &mut Sprite, &mut Transform)>,
+) {
+    for (entity, switch, mut sprite, mut transform) in &mut query {
+        if !(switch.is_added() || switches.is_changed()) {
+            continue;
+        }
+        sprite.color = pair_color(switches.channel(switch.position).unwrap_or(0).saturating_add(1));
+        let Some(open) = switch.gate_open else {
+            continue;
+        };
+        if !switch.is_added() {
+            continue;
+        }
+        if let Some(was_open) = gate_states.0.insert(switch.position, open).filter(|&was_open| was_open != open) {
+            transform.scale = gate_scale(was_open);
+            let tween = Tween::new(
+                EaseFunction::QuadraticInOut,
+                Duration::from_millis(DUCK_MOVE_MILI_SECS),
+                TransformScaleLens {
+                    start: gate_scale(was_open),
+                    end: gate_scale(open),
+                },
+            );
+            commands.entity(entity).insert(Animator::new(tween));
+        }
+    }
+}
+
+/// 冰块的贴图，记着它在哪一格，推动后用来播放滑动动画
+#[derive(Component)]
+pub struct IceBlockSprite(pub (usize, usize));
+
+/// 刚开始一关时每只鸭子的状态。主人按关卡文件里的 `;duck` 行，没写这些行时第一只归 Player1，
+/// 第二只归 Player2，其余的没有主人；每个玩家一开始操作自己的第一只
+pub fn initial_record(
+    level: &[Vec<char>],
+    owners: &[((usize, usize), u8)],
+    play_mode: PlayMode,
+    selected_characters: &SelectedCharacters,
+    characters: &Characters,
+) -> BreadRecord {
+    let mut record = BreadRecord::new();
+    // 已经有正在操作的鸭子的玩家
+    let mut active_controllers = Vec::new();
+    for (row_index, row) in level.iter().enumerate() {
+        for (col_index, &ch) in row.iter().enumerate() {
+            let Some(object_type) = SymbolType::from_symbol(ch).filter(|symbol| symbol.is_duck()) else {
+                continue;
+            };
+            let position = (row_index, col_index);
+            let owner = if owners.is_empty() {
+                match record.len() {
+                    0 => 1,
+                    1 => 2,
+                    _ => NEUTRAL,
+                }
+            } else {
+                owners
+                    .iter()
+                    .find(|(owned, _)| *owned == position)
+                    .map_or(NEUTRAL, |(_, owner)| *owner)
+            };
+            let controller = controller(owner, play_mode);
+            let active = controller != NEUTRAL && !active_controllers.contains(&controller);
+            if active {
+                active_controllers.push(controller);
+            }
+            let stats = characters.stats(owner_character(owner, selected_characters));
+            // 吃饱的鸭子算吃了一个面包，但肚子是满的
+            let stuffed = matches!(object_type, SymbolType::StuffedDuckOnIce | SymbolType::DuckOnWater);
+            record.push((
+                position,
+                DuckRecord {
+                    bread_count: u32::from(stuffed),
+                    loaves: if stuffed { stats.belly_capacity } else { 0 },
+                    ability_uses: stats.ability_uses(),
+                    owner,
+                    active,
+                    ..default()
+                },
+            ));
+        }
+    }
+    record
+}
+
+// 单人模式下只有一个玩家，由他控制所有的鸭子
+fn controller(owner: u8, play_mode: PlayMode) -> u8 {
+    if play_mode == PlayMode::Solo {
+        1
+    } else {
+        owner
+    }
+}
+
+fn owner_character(owner: u8, selected_characters: &SelectedCharacters) -> CharacterType {
+    match owner {
+        1 => selected_characters.player1.unwrap_or_default(),
+        2 => selected_characters.player2.unwrap_or_default(),
+        _ => CharacterType::default(),
+    }
+}
+
 #[derive(Bundle)]
 struct DuckBundle {
     sprite: SpriteBundle,
@@ -325,28 +1013,21 @@ pub fn spawn_sprites(
     image_assets: &Res<ImageAssets>,
     level_index: usize,
     bread_count: &mut ResMut<BreadCount>,
-    events: &mut EventWriter<Won>,
     should_respawn_duck: bool,
     selected_characters: &Res<SelectedCharacters>,
+    characters: &Characters,
+    play_mode: PlayMode,
+    // 每只鸭子的主人和肚子里的东西，刚开始一关时用 initial_record
+    bread_record: &[((usize, usize), DuckRecord)],
 ) {
     bread_count.0 = 0;
-    let mut duck_index = 0;
 
     for (row_index, row) in level.iter().enumerate() {
         for (col_index, &ch) in row.iter().enumerate() {
             let position = logic_position_to_translation((row_index, col_index));
 
-            let object_type = match ch {
-                '@' => SymbolType::Wall,
-                '#' => SymbolType::Ice,
-                '^' => SymbolType::BrokenIce,
-                'D' => SymbolType::DuckOnIce,
-                'B' => SymbolType::BreadOnIce,
-                '*' => SymbolType::BreakingIce,
-                'P' => SymbolType::DuckOnWater,
-                'O' => SymbolType::DuckOnBreakingIce,
-                'Q' => SymbolType::StuffedDuckOnIce,
-                _ => continue,
+            let Some(object_type) = SymbolType::from_symbol(ch) else {
+                continue;
             };
 
             match object_type {
@@ -364,55 +1045,148 @@ pub fn spawn_sprites(
                     spawn_object(commands, position, image_assets.ice.clone());
                     spawn_upper_object(commands, position, image_assets.bread.clone());
                 }
+                SymbolType::GoldenBread | SymbolType::HeavyBread => {
+                    bread_count.0 += 1;
+                    spawn_object(commands, position, image_assets.ice.clone());
+                    spawn_tinted_upper_object(
+                        commands,
+                        position,
+                        image_assets.bread.clone(),
+                        object_type.bread_tint(),
+                    );
+                }
+                SymbolType::Pepper => {
+                    spawn_object(commands, position, image_assets.ice.clone());
+                    spawn_upper_object(commands, position, image_assets.pepper.clone());
+                }
                 SymbolType::BreakingIce => {
                     spawn_object(commands, position, image_assets.breaking_ice.clone());
                 }
+                SymbolType::CrackedIce => {
+                    spawn_object(commands, position, image_assets.cracked_ice.clone());
+                }
+                SymbolType::SturdyIce => {
+                    spawn_object(commands, position, image_assets.sturdy_ice.clone());
+                }
+                SymbolType::ArrowUp
+                | SymbolType::ArrowDown
+                | SymbolType::ArrowLeft
+                | SymbolType::ArrowRight => {
+                    spawn_object(commands, position, image_assets.ice.clone());
+                    let direction = object_type.arrow_direction().unwrap_or_default();
+                    commands.spawn((
+                        SpriteBundle {
+                            texture: image_assets.arrow_tile.clone(),
+                            transform: Transform {
+                                translation: Vec3::new(position.x, position.y, position.z + 1.0),
+                                rotation: arrow_rotation(direction),
+                                scale: Vec3::new(1.0 * RESIZE, 1.0 * RESIZE, 1.0),
+                            },
+                            ..default()
+                        },
+                        Object,
+                    ));
+                }
+                SymbolType::Portal(pair) => {
+                    spawn_object(commands, position, image_assets.ice.clone());
+                    commands.spawn((
+                        SpriteBundle {
+                            sprite: Sprite {
+                                color: pair_color(pair),
+                                ..default()
+                            },
+                            texture: image_assets.portal.clone(),
+                            transform: Transform {
+                                translation: Vec3::new(position.x, position.y, position.z + 1.0),
+                                rotation: Quat::IDENTITY,
+                                scale: Vec3::new(1.0 * RESIZE, 1.0 * RESIZE, 1.0),
+                            },
+                            ..default()
+                        },
+                        Object,
+                    ));
+                }
+                SymbolType::Plate | SymbolType::TogglePlate | SymbolType::ClosedGate | SymbolType::OpenGate => {
+                    spawn_object(commands, position, image_assets.ice.clone());
+                    let gate_open = match object_type {
+                        SymbolType::ClosedGate => Some(false),
+                        SymbolType::OpenGate => Some(true),
+                        _ => None,
+                    };
+                    let texture = match object_type {
+                        SymbolType::Plate => image_assets.plate.clone(),
+                        SymbolType::TogglePlate => image_assets.toggle_plate.clone(),
+                        _ => image_assets.gate.clone(),
+                    };
+                    commands.spawn((
+                        SpriteBundle {
+                            texture,
+                            transform: Transform {
+                                translation: Vec3::new(position.x, position.y, position.z + 1.0),
+                                rotation: Quat::IDENTITY,
+                                scale: gate_open.map_or(Vec3::new(1.0 * RESIZE, 1.0 * RESIZE, 1.0), gate_scale),
+                            },
+                            ..default()
+                        },
+                        Object,
+                        SwitchSprite {
+                            position: (row_index, col_index),
+                            gate_open,
+                        },
+                    ));
+                }
+                SymbolType::IceBlock => {
+                    spawn_object(commands, position, image_assets.ice.clone());
+                    commands.spawn((
+                        SpriteBundle {
+                            texture: image_assets.ice_block.clone(),
+                            transform: Transform {
+                                translation: Vec3::new(position.x, position.y, position.z + 1.0),
+                                rotation: Quat::IDENTITY,
+                                scale: Vec3::new(1.0 * RESIZE, 1.0 * RESIZE, 1.0),
+                            },
+                            ..default()
+                        },
+                        Object,
+                        IceBlockSprite((row_index, col_index)),
+                    ));
+                }
                 SymbolType::DuckOnIce
                 | SymbolType::StuffedDuckOnIce
                 | SymbolType::DuckOnWater
-                | SymbolType::DuckOnBreakingIce => {
+                | SymbolType::DuckOnBreakingIce
+                | SymbolType::DuckOnCrackedIce
+                | SymbolType::DuckOnThinIce => {
                     // 底层 tile
                     let base_sprite = match object_type {
                         SymbolType::DuckOnWater => image_assets.water.clone(),
-                        SymbolType::DuckOnBreakingIce => image_assets.breaking_ice.clone(),
+                        SymbolType::DuckOnBreakingIce | SymbolType::DuckOnThinIce => {
+                            image_assets.breaking_ice.clone()
+                        }
+                        SymbolType::DuckOnCrackedIce => image_assets.cracked_ice.clone(),
                         _ => image_assets.ice.clone(),
                     };
                     spawn_object(commands, position, base_sprite);
 
                     if should_respawn_duck {
-                        duck_index += 1;
-
                         let stuffed = matches!(
                             object_type,
                             SymbolType::StuffedDuckOnIce | SymbolType::DuckOnWater
                         );
 
-                        let mut character_type = CharacterType::Duck;
-                        let mut insert_player1 = false;
-                        let mut insert_player2 = false;
-
-                        match duck_index {
-                            1 => {
-                                insert_player1 = true;
-                                character_type = selected_characters.player1.unwrap_or(CharacterType::Duck);
-                            }
-                            2 => {
-                                insert_player2 = true;
-                                character_type = selected_characters.player2.unwrap_or(CharacterType::Duck);
-                            }
-                            _ => {}
-                        }
+                        // 记录里没有的鸭子没有主人
+                        let record = bread_record
+                            .iter()
+                            .find(|(position, _)| *position == (row_index, col_index))
+                            .map(|(_, record)| *record)
+                            .unwrap_or_default();
+                        let owner = record.owner;
+                        let character_type = owner_character(owner, selected_characters);
+                        let controller = controller(owner, play_mode);
+                        let active = controller != NEUTRAL && record.active;
+                        let stats = characters.stats(character_type);
 
-                        let sprite = match (character_type, stuffed) {
-                            (CharacterType::Duck, false) => image_assets.duck.clone(),
-                            (CharacterType::Duck, true) => image_assets.stuffed_duck.clone(),
-                            (CharacterType::Cat, false) => image_assets.cat.clone(),
-                            (CharacterType::Cat, true) => image_assets.stuffed_cat.clone(),
-                            (CharacterType::Bunny, false) => image_assets.bunny.clone(),
-                            (CharacterType::Bunny, true) => image_assets.stuffed_bunny.clone(),
-                            (CharacterType::Chick, false) => image_assets.chick.clone(),
-                            (CharacterType::Chick, true) => image_assets.stuffed_chick.clone(),
-                        };
+                        let sprite = characters.sprite(character_type, stuffed);
 
                         let mut entity = commands.spawn(DuckBundle {
                             sprite: SpriteBundle {
@@ -422,23 +1196,37 @@ pub fn spawn_sprites(
                                     ..default()
                                 },
                                 texture: sprite,
+                                sprite: Sprite {
+                                    color: duck_tint(record.heavy),
+                                    ..default()
+                                },
                                 ..default()
                             },
                             marker: CommonDuck {
                                 logic_position: (row_index, col_index),
-                                can_move: true,
-                                bread_count: if stuffed { 1 } else { 0 },
+                                // 掉进水里的鸭子不能再动
+                                can_move: !matches!(object_type, SymbolType::DuckOnWater),
+                                bread_count: record.bread_count,
+                                loaves: record.loaves,
+                                belly_capacity: stats.belly_capacity,
+                                heavy: record.heavy,
+                                peppers: record.peppers,
+                                ability: stats.ability,
+                                ability_uses: record.ability_uses,
+                                owner,
                             },
                             obj: Object,
                         });
+                        entity.insert(CharacterTag(character_type));
 
-                        if insert_player1 {
-                            entity
-                            .insert(Player1);
-                        }
-                        if insert_player2 {
-                            entity
-                            .insert(Player2);
+                        match (active, controller) {
+                            (true, 1) => {
+                                entity.insert(Player1);
+                            }
+                            (true, 2) => {
+                                entity.insert(Player2);
+                            }
+                            _ => {}
                         }
 
                     }
@@ -447,10 +1235,6 @@ pub fn spawn_sprites(
         }
     }
     println!("===> SelectedCharacters: P1={:?}, P2={:?}", selected_characters.player1, selected_characters.player2);
-
-    if bread_count.0 == 0 {
-            events.send(Won);
-    }
 }
 
 
@@ -477,6 +1261,9 @@ pub struct RestartLevelEvent;
 #[derive(Event)]
 pub struct UndoLevelEvent {
     pub level_data: Level, // 用于直接覆盖当前 Level
+    pub bread_record: BreadRecord,
+    /// 回到那一步时的步数，`None` 表示不改步数
+    pub moves: Option<MoveCounter>,
 }
 
 #[derive(Event)]
@@ -496,13 +1283,14 @@ fn level_restart(
     image_assets: Res<ImageAssets>,
     bread_count: ResMut<BreadCount>,
     total_bread_count: ResMut<TotalBreadCount>,
-    level_index: Res<CurrentLevelIndex>,
-    levels: Res<Levels>,
-    level_stack: ResMut<LevelStack>,
-    bread_sum_record_stack: ResMut<BreadSumRecordStack>,
+    loader: LevelLoader,
+    undo_stacks: UndoStacks,
     // event
     events: EventWriter<Won>,
-    selected_characters: Res<SelectedCharacters>
+    selected_characters: Res<SelectedCharacters>,
+    characters: Res<Characters>,
+    play_mode: Res<PlayMode>,
+    move_counter: ResMut<MoveCounter>,
 ) {
     if restart.read().next().is_some() {
         // Despawn level elements
@@ -516,14 +1304,15 @@ fn level_restart(
         spawn_level(
             commands,
             image_assets,
-            level_index,
+            loader,
             bread_count,
             total_bread_count,
-            levels,
-            level_stack,
-            bread_sum_record_stack,
+            undo_stacks,
             events,
             selected_characters,
+            characters,
+            play_mode,
+            move_counter,
         );
     }
 }
@@ -538,12 +1327,14 @@ fn load_other_level(
     image_assets: Res<ImageAssets>,
     bread_count: ResMut<BreadCount>,
     total_bread_count: ResMut<TotalBreadCount>,
-    levels: Res<Levels>,
-    level_stack: ResMut<LevelStack>,
-    bread_sum_record_stack: ResMut<BreadSumRecordStack>,
+    loader: LevelLoader,
+    undo_stacks: UndoStacks,
     // event
     events: EventWriter<Won>,
     selected_characters: Res<SelectedCharacters>,
+    characters: Res<Characters>,
+    play_mode: Res<PlayMode>,
+    move_counter: ResMut<MoveCounter>,
 ) {
     if level_index.is_changed() {
         // clear the scene
@@ -553,14 +1344,15 @@ fn load_other_level(
         spawn_level(
             commands,
             image_assets,
-            level_index,
+            loader,
             bread_count,
             total_bread_count,
-            levels,
-            level_stack,
-            bread_sum_record_stack,
+            undo_stacks,
             events,
             selected_characters,
+            characters,
+            play_mode,
+            move_counter,
         )
     }
 }
@@ -590,9 +1382,15 @@ fn undo_the_level(
     mut events: EventWriter<Won>,
     object_query: Query<Entity, With<Object>>,
     selected_characters: Res<SelectedCharacters>,
+    characters: Res<Characters>,
+    play_mode: Res<PlayMode>,
+    mut move_counter: ResMut<MoveCounter>,
 ) {
-    for UndoLevelEvent { level_data } in undo.read() {
+    for UndoLevelEvent { level_data, bread_record, moves } in undo.read() {
         level.0 = level_data.0.clone();
+        if let Some(moves) = moves {
+            *move_counter = *moves;
+        }
 
         for entity in object_query.iter() {
             commands.entity(entity).despawn();
@@ -604,10 +1402,13 @@ fn undo_the_level(
             &image_assets,
             level_index.0,
             &mut bread_count,
-            &mut events,
             true, // undo flag
             &selected_characters,
+            &characters,
+            *play_mode,
+            bread_record,
         );
+        send_won_if_cleared(&bread_count, &mut events);
     }
 }
 
@@ -630,14 +1431,30 @@ pub fn get_entity_on_logic_position(
     None
 }
 
+fn latch_level_cleared(
+    mut started: EventReader<LevelStarted>,
+    mut won: EventReader<Won>,
+    mut latch: ResMut<ClearedLatch>,
+    mut cleared: EventWriter<LevelCleared>,
+) {
+    if started.read().next().is_some() {
+        latch.0 = false;
+    }
+    if won.read().next().is_some() && !latch.0 {
+        latch.0 = true;
+        cleared.send(LevelCleared);
+    }
+}
+
 fn handle_completion(
     mut events: EventReader<Won>,
     level_index: Res<CurrentLevelIndex>,
     levels: Res<Levels>,
+    play_mode: Res<PlayMode>,
     mut next_state: ResMut<NextState<GameStates>>,
 ) {
     for _ in events.read() {
-        if level_index.0 == levels.levels.len() {
+        if level_index.0 == levels.levels.len() && *play_mode != PlayMode::Replay {
             // 如果是最后一关，进入庆祝状态
             next_state.set(GameStates::Celebration);
         }
@@ -647,3 +1464,99 @@ fn handle_completion(
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(rows: &[&str]) -> Level {
+        Level(rows.iter().map(|row| row.chars().collect()).collect())
+    }
+
+    // 和 slip 一样先写好鸭子的新位置，再重新计算闸门
+    fn move_duck(level: &mut Level, switches: &Switches, from: (usize, usize), to: (usize, usize)) {
+        level.0[from.0][from.1] = SymbolType::Ice.get_symbol();
+        level.0[to.0][to.1] = SymbolType::DuckOnIce.get_symbol();
+        switches.update(level, from, to);
+    }
+
+    #[test]
+    fn meta_lines_are_parsed() {
+        let meta = LevelMeta::parse(";par 8\r\n;channel 3 5 1\r\n;duck 1 2 2\r\n@@@@\r\n;unknown 1\r\n@DB@");
+        assert_eq!(meta.par, Some(8));
+        assert_eq!(meta.channels, vec![((3, 5), 1)]);
+        assert_eq!(meta.owners, vec![((1, 2), 2)]);
+    }
+
+    #[test]
+    fn meta_lines_round_trip() {
+        let meta = LevelMeta::parse(";par 4\r\n;channel 3 4 1\r\n;channel 3 5 1\r\n;duck 1 1 2\r\n@@@");
+        let again = LevelMeta::parse(&meta.lines().join("\r\n"));
+        assert_eq!(again.par, meta.par);
+        assert_eq!(again.channels, meta.channels);
+        assert_eq!(again.owners, meta.owners);
+    }
+
+    #[test]
+    fn initial_record_follows_duck_lines() {
+        let grid = level(&["@DDDQ@"]).0;
+        let owners = [((0, 1), 2), ((0, 2), 1), ((0, 3), 2)];
+        let selected = SelectedCharacters::default();
+        let characters = crate::game::character::tests::characters();
+        let record = initial_record(&grid, &owners, PlayMode::Local, &selected, &characters);
+        let owner_and_active: Vec<_> = record.iter().map(|(_, duck)| (duck.owner, duck.active)).collect();
+        // 每个玩家一开始操作自己的第一只，没写主人的没有主人
+        assert_eq!(owner_and_active, [(2, true), (1, true), (2, false), (NEUTRAL, false)]);
+        assert_eq!((record[3].1.bread_count, record[3].1.loaves), (1, 1));
+        // 没写 ;duck 行时前两只分给两个玩家；单人模式下只操作一只
+        let record = initial_record(&grid, &[], PlayMode::Solo, &selected, &characters);
+        let owner_and_active: Vec<_> = record.iter().map(|(_, duck)| (duck.owner, duck.active)).collect();
+        assert_eq!(owner_and_active, [(1, true), (2, false), (NEUTRAL, false), (NEUTRAL, false)]);
+    }
+
+    #[test]
+    fn demo_level_gives_each_player_two_ducks() {
+        let levels = Levels::default();
+        let meta = levels.meta(levels.levels.len());
+        let owned_by = |player_id: u8| meta.owners.iter().filter(|(_, owner)| *owner == player_id).count();
+        assert_eq!((owned_by(1), owned_by(2)), (2, 2));
+        // 下标超出范围时没有元数据
+        assert!(levels.meta(0).owners.is_empty());
+    }
+
+    #[test]
+    fn pressure_plate_holds_gates_open() {
+        let mut level = level(&["@D_#|#@"]);
+        let switches = Switches::new(&level.0, &LevelMeta::default());
+        move_duck(&mut level, &switches, (0, 1), (0, 2));
+        assert_eq!(level.0[0], "@#D#=#@".chars().collect::<Vec<_>>());
+        move_duck(&mut level, &switches, (0, 2), (0, 3));
+        assert_eq!(level.0[0], "@#_D|#@".chars().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn toggle_plate_flips_gates_of_its_channel_only() {
+        let mut level = level(&["@D%#=|@"]);
+        let meta = LevelMeta {
+            channels: vec![((0, 2), 1), ((0, 4), 1)],
+            ..default()
+        };
+        let switches = Switches::new(&level.0, &meta);
+        move_duck(&mut level, &switches, (0, 1), (0, 2));
+        assert_eq!(level.0[0], "@#D#||@".chars().collect::<Vec<_>>());
+        // 离开开关板不会再切换
+        move_duck(&mut level, &switches, (0, 2), (0, 3));
+        assert_eq!(level.0[0], "@#%D||@".chars().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn gate_under_a_duck_stays_open() {
+        let mut level = level(&["@D%#=#@"]);
+        let switches = Switches::new(&level.0, &LevelMeta::default());
+        level.0[0][4] = SymbolType::DuckOnIce.get_symbol();
+        move_duck(&mut level, &switches, (0, 1), (0, 2));
+        assert_eq!(level.0[0][4], 'D');
+        move_duck(&mut level, &switches, (0, 4), (0, 5));
+        assert_eq!(level.0[0][4], '=');
+    }
+}
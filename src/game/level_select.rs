@@ -0,0 +1,593 @@
+use super::{
+    cursor::ArrowHint,
+    level::{arrow_rotation, load_level, CurrentLevelIndex, Level, Levels, SymbolType},
+    progress::{star_rating, LevelProgress},
+    ui::{GameHints, LevelTitle, MutUI, StuffedDucksCount},
+    *,
+};
+use crate::networking::{ClientMessage, InboundServerMessage, LevelProposal, ServerLink, ServerMessage};
+
+// Picking a level between character selection and the game, or with L
+// during the game. Only levels unlocked in the player's progress can be
+// picked. In a two player game the pick is a proposal the other player has
+// to accept, the server then starts the level for everyone.
+
+pub struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LevelProposalView>()
+            .add_systems(Update, apply_level_proposals)
+            .add_systems(
+                Update,
+                open_level_select
+                    .run_if(in_state(GameStates::Next))
+                    .run_if(not(resource_equals(PlayMode::Replay))),
+            )
+            .add_systems(OnEnter(GameStates::LevelSelect), (clear_level_scene, setup_level_select))
+            .add_systems(
+                Update,
+                (
+                    level_select_input,
+                    level_button_interaction,
+                    show_proposal_dialog,
+                    answer_proposal,
+                )
+                    .chain()
+                    .run_if(in_state(GameStates::LevelSelect)),
+            )
+            .add_systems(OnExit(GameStates::LevelSelect), cleanup_level_select);
+    }
+}
+
+const LEVELS_PER_ROW: usize = 7;
+const CARD_WIDTH: f32 = 160.0;
+const CARD_HEIGHT: f32 = 150.0;
+const CARD_GAP: f32 = 15.0;
+const THUMBNAIL_WIDTH: f32 = 140.0;
+const THUMBNAIL_HEIGHT: f32 = 84.0;
+
+const NORMAL_BUTTON: Color = MY_ORANGE;
+const HOVERED_BUTTON: Color =
+    Color::srgb(222.0 / 255.0 + 0.1, 112.0 / 255.0 + 0.1, 40.0 / 255.0 + 0.1);
+const PRESSED_BUTTON: Color = Color::srgb(0.75, 0.75, 0.75);
+const LOCKED_LEVEL: Color = MY_BROWN;
+const LOCKED_TINT: Color = Color::srgba(0.4, 0.4, 0.4, 0.8);
+const STAR_OFF: Color = Color::srgba(0.3, 0.3, 0.3, 0.6);
+
+/// 客户端看到的选关提议，由 LevelProposal 消息更新
+#[derive(Resource, Default)]
+pub struct LevelProposalView {
+    pub proposal: Option<LevelProposal>,
+}
+
+fn apply_level_proposals(mut inbound: EventReader<InboundServerMessage>, mut view: ResMut<LevelProposalView>) {
+    for InboundServerMessage(message) in inbound.read() {
+        if let ServerMessage::LevelProposal { proposal } = message {
+            view.proposal = *proposal;
+        }
+    }
+}
+
+#[derive(Component)]
+struct LevelSelectEntity;
+
+#[derive(Component, Clone, Copy)]
+enum LevelSelectButton {
+    Level(usize),
+    // Back to the current level
+    Play,
+}
+
+#[derive(Component)]
+struct ProposalDialog;
+
+#[derive(Component, Clone, Copy)]
+struct ProposalAnswer(bool);
+
+// Both players go to the level select together
+fn open_level_select(keyboard: Res<ButtonInput<KeyCode>>, mut link: ServerLink) {
+    if keyboard.just_pressed(KeyCode::KeyL) {
+        link.send(ClientMessage::StateChangeRequest(GameStates::LevelSelect));
+    }
+}
+
+// The board stays around when leaving Next, it is spawned again on the way back
+pub fn clear_level_scene(
+    mut commands: Commands,
+    query: Query<
+        Entity,
+        Or<(
+            With<level::Object>,
+            With<ArrowHint>,
+            With<GameHints>,
+            With<LevelTitle>,
+            With<StuffedDucksCount>,
+            With<MutUI>,
+        )>,
+    >,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn setup_level_select(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    image_assets: Res<ImageAssets>,
+    levels: Res<Levels>,
+    progress: Res<LevelProgress>,
+    current_level: Res<CurrentLevelIndex>,
+) {
+    let font = asset_server.load("fonts/NotJamChunky8.ttf");
+    let grid_width = LEVELS_PER_ROW as f32 * (CARD_WIDTH + CARD_GAP);
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(25.0),
+                    padding: UiRect::top(Val::Px(40.0)),
+                    ..default()
+                },
+                ..default()
+            },
+            LevelSelectEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "SELECT LEVEL",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 50.0,
+                    color: MY_ORANGE,
+                },
+            ));
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(grid_width),
+                        flex_wrap: FlexWrap::Wrap,
+                        justify_content: JustifyContent::Center,
+                        row_gap: Val::Px(CARD_GAP),
+                        column_gap: Val::Px(CARD_GAP),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for level_index in 1..=levels.levels.len() {
+                        let Ok(level) = load_level(level_index, Res::clone(&levels)) else {
+                            continue;
+                        };
+                        let status = LevelStatus::new(&progress, &levels, level_index);
+                        spawn_level_card(parent, &font, &image_assets, level_index, &level, &status);
+                    }
+                });
+
+            spawn_play_button(parent, &font, current_level.0);
+        });
+}
+
+// What the card says under the thumbnail
+struct LevelStatus {
+    unlocked: bool,
+    best_moves: Option<u32>,
+    stars: Option<u32>,
+}
+
+impl LevelStatus {
+    fn new(progress: &LevelProgress, levels: &Levels, level_index: usize) -> Self {
+        let best_moves = progress.best_moves.get(&level_index).copied();
+        let stars = best_moves.zip(levels.meta(level_index).par).map(|(moves, par)| star_rating(moves, par));
+        LevelStatus {
+            unlocked: progress.is_unlocked(level_index),
+            best_moves: best_moves.filter(|_| progress.is_completed(level_index)),
+            stars,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match (self.unlocked, self.best_moves) {
+            (false, _) => "Locked".to_string(),
+            (true, Some(moves)) => format!("Best {}", moves),
+            (true, None) => "New".to_string(),
+        }
+    }
+}
+
+fn spawn_level_card(
+    parent: &mut ChildBuilder,
+    font: &Handle<Font>,
+    image_assets: &ImageAssets,
+    level_index: usize,
+    level: &Level,
+    status: &LevelStatus,
+) {
+    let style = Style {
+        width: Val::Px(CARD_WIDTH),
+        height: Val::Px(CARD_HEIGHT),
+        flex_direction: FlexDirection::Column,
+        align_items: AlignItems::Center,
+        justify_content: JustifyContent::Center,
+        row_gap: Val::Px(6.0),
+        ..default()
+    };
+
+    // Locked levels can't be clicked
+    if status.unlocked {
+        parent
+            .spawn((
+                ButtonBundle {
+                    style,
+                    background_color: NORMAL_BUTTON.into(),
+                    ..default()
+                },
+                LevelSelectButton::Level(level_index),
+            ))
+            .with_children(|parent| spawn_card_contents(parent, font, image_assets, level_index, level, status));
+    } else {
+        parent
+            .spawn(NodeBundle {
+                style,
+                background_color: LOCKED_LEVEL.into(),
+                ..default()
+            })
+            .with_children(|parent| spawn_card_contents(parent, font, image_assets, level_index, level, status));
+    }
+}
+
+fn spawn_card_contents(
+    parent: &mut ChildBuilder,
+    font: &Handle<Font>,
+    image_assets: &ImageAssets,
+    level_index: usize,
+    level: &Level,
+    status: &LevelStatus,
+) {
+    let text_style = |font_size: f32| TextStyle {
+        font: font.clone(),
+        font_size,
+        color: if status.unlocked { Color::WHITE } else { Color::srgb(0.6, 0.6, 0.6) },
+    };
+    spawn_thumbnail(parent, image_assets, level, status.unlocked);
+    parent.spawn(TextBundle::from_section(format!("Level{}", level_index), text_style(16.0)));
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                column_gap: Val::Px(4.0),
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(status.describe(), text_style(12.0)));
+            // Stars against par, drawn as bread like on the level complete screen
+            if let Some(stars) = status.stars {
+                for star in 1..=3 {
+                    let color = if star <= stars { Color::WHITE } else { STAR_OFF };
+                    parent.spawn(ImageBundle {
+                        style: Style {
+                            width: Val::Px(14.0),
+                            height: Val::Px(14.0),
+                            ..default()
+                        },
+                        image: UiImage::new(image_assets.bread.clone()).with_color(color),
+                        ..default()
+                    });
+                }
+            }
+        });
+}
+
+// The base tile and whatever stands on it
+fn thumbnail_tiles(symbol: SymbolType, image_assets: &ImageAssets) -> (Handle<Image>, Option<Handle<Image>>) {
+    match symbol {
+        SymbolType::Wall => (image_assets.wall.clone(), None),
+        SymbolType::Ice => (image_assets.ice.clone(), None),
+        SymbolType::BrokenIce => (image_assets.water.clone(), None),
+        SymbolType::BreakingIce => (image_assets.breaking_ice.clone(), None),
+        SymbolType::BreadOnIce | SymbolType::GoldenBread | SymbolType::HeavyBread => {
+            (image_assets.ice.clone(), Some(image_assets.bread.clone()))
+        }
+        SymbolType::Pepper => (image_assets.ice.clone(), Some(image_assets.pepper.clone())),
+        SymbolType::DuckOnIce => (image_assets.ice.clone(), Some(image_assets.duck.clone())),
+        SymbolType::StuffedDuckOnIce => (image_assets.ice.clone(), Some(image_assets.stuffed_duck.clone())),
+        SymbolType::DuckOnWater => (image_assets.water.clone(), Some(image_assets.stuffed_duck.clone())),
+        SymbolType::DuckOnBreakingIce | SymbolType::DuckOnThinIce => {
+            (image_assets.breaking_ice.clone(), Some(image_assets.duck.clone()))
+        }
+        SymbolType::DuckOnCrackedIce => (image_assets.cracked_ice.clone(), Some(image_assets.duck.clone())),
+        SymbolType::CrackedIce => (image_assets.cracked_ice.clone(), None),
+        SymbolType::SturdyIce => (image_assets.sturdy_ice.clone(), None),
+        SymbolType::ArrowUp | SymbolType::ArrowDown | SymbolType::ArrowLeft | SymbolType::ArrowRight => {
+            (image_assets.ice.clone(), Some(image_assets.arrow_tile.clone()))
+        }
+        SymbolType::Portal(_) => (image_assets.ice.clone(), Some(image_assets.portal.clone())),
+        SymbolType::IceBlock => (image_assets.ice.clone(), Some(image_assets.ice_block.clone())),
+        SymbolType::Plate => (image_assets.ice.clone(), Some(image_assets.plate.clone())),
+        SymbolType::TogglePlate => (image_assets.ice.clone(), Some(image_assets.toggle_plate.clone())),
+        SymbolType::ClosedGate => (image_assets.ice.clone(), Some(image_assets.gate.clone())),
+        // 打开的闸门只画冰面
+        SymbolType::OpenGate => (image_assets.ice.clone(), None),
+    }
+}
+
+// A small copy of the level drawn with the same sprites as the game
+fn spawn_thumbnail(parent: &mut ChildBuilder, image_assets: &ImageAssets, level: &Level, unlocked: bool) {
+    let rows = level.0.len().max(1);
+    let columns = level.0.iter().map(|row| row.len()).max().unwrap_or(0).max(1);
+    let cell = (THUMBNAIL_WIDTH / columns as f32).min(THUMBNAIL_HEIGHT / rows as f32).floor().max(1.0);
+    let tint = if unlocked { Color::WHITE } else { LOCKED_TINT };
+    let cell_style = || Style {
+        width: Val::Px(cell),
+        height: Val::Px(cell),
+        ..default()
+    };
+
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Px(THUMBNAIL_WIDTH),
+                height: Val::Px(THUMBNAIL_HEIGHT),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            for row in &level.0 {
+                parent
+                    .spawn(NodeBundle::default())
+                    .with_children(|parent| {
+                        for &ch in row {
+                            let Some(symbol) = SymbolType::from_symbol(ch) else {
+                                parent.spawn(NodeBundle {
+                                    style: cell_style(),
+                                    ..default()
+                                });
+                                continue;
+                            };
+                            let (base, upper) = thumbnail_tiles(symbol, image_assets);
+                            parent
+                                .spawn(ImageBundle {
+                                    style: cell_style(),
+                                    image: UiImage::new(base).with_color(tint),
+                                    ..default()
+                                })
+                                .with_children(|parent| {
+                                    if let Some(upper) = upper {
+                                        parent.spawn(ImageBundle {
+                                            style: cell_style(),
+                                            // 特殊面包在解锁的关卡里照样染色
+                                            image: UiImage::new(upper).with_color(if unlocked {
+                                                symbol.bread_tint()
+                                            } else {
+                                                tint
+                                            }),
+                                            transform: Transform::from_rotation(
+                                                symbol.arrow_direction().map_or(Quat::IDENTITY, arrow_rotation),
+                                            ),
+                                            ..default()
+                                        });
+                                    }
+                                });
+                        }
+                    });
+            }
+        });
+}
+
+fn spawn_play_button(parent: &mut ChildBuilder, font: &Handle<Font>, current_level: usize) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(360.0),
+                    height: Val::Px(60.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: NORMAL_BUTTON.into(),
+                ..default()
+            },
+            LevelSelectButton::Play,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                format!("Play Level{}", current_level),
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 24.0,
+                    color: Color::WHITE,
+                },
+            ));
+        });
+}
+
+// Esc goes back to the current level, unless there is a proposal to answer
+fn level_select_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    view: Res<LevelProposalView>,
+    current_level: Res<CurrentLevelIndex>,
+    mut link: ServerLink,
+) {
+    if view.proposal.is_none() && keyboard.just_pressed(KeyCode::Escape) {
+        link.send(ClientMessage::SelectLevel(*current_level));
+    }
+}
+
+fn level_button_interaction(
+    mut interaction_query: Query<
+        (&Interaction, &mut BackgroundColor, &LevelSelectButton),
+        Changed<Interaction>,
+    >,
+    view: Res<LevelProposalView>,
+    current_level: Res<CurrentLevelIndex>,
+    mut link: ServerLink,
+) {
+    for (interaction, mut color, button) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *color = PRESSED_BUTTON.into();
+                // One proposal at a time
+                if view.proposal.is_some() {
+                    continue;
+                }
+                let level_index = match *button {
+                    LevelSelectButton::Level(level_index) => CurrentLevelIndex(level_index),
+                    LevelSelectButton::Play => *current_level,
+                };
+                link.send(ClientMessage::SelectLevel(level_index));
+            }
+            Interaction::Hovered => {
+                *color = HOVERED_BUTTON.into();
+            }
+            Interaction::None => {
+                *color = NORMAL_BUTTON.into();
+            }
+        }
+    }
+}
+
+fn show_proposal_dialog(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    view: Res<LevelProposalView>,
+    query: Query<Entity, With<ProposalDialog>>,
+) {
+    if !view.is_changed() {
+        return;
+    }
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let Some(proposal) = view.proposal else {
+        return;
+    };
+
+    let font = asset_server.load("fonts/NotJamChunky8.ttf");
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(15.0),
+                    ..default()
+                },
+                background_color: Color::srgba(0.0, 0.0, 0.0, 0.7).into(),
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+            ProposalDialog,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                format!("P{} wants to play Level{}", proposal.player_id, proposal.level_index),
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 30.0,
+                    color: MY_ORANGE,
+                },
+            ));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(20.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for (label, accept) in [("Accept (Enter)", true), ("Decline (Esc)", false)] {
+                        parent
+                            .spawn((
+                                ButtonBundle {
+                                    style: Style {
+                                        width: Val::Px(260.0),
+                                        height: Val::Px(50.0),
+                                        justify_content: JustifyContent::Center,
+                                        align_items: AlignItems::Center,
+                                        ..default()
+                                    },
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                                ProposalAnswer(accept),
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle::from_section(
+                                    label,
+                                    TextStyle {
+                                        font: font.clone(),
+                                        font_size: 20.0,
+                                        color: Color::WHITE,
+                                    },
+                                ));
+                            });
+                    }
+                });
+        });
+}
+
+// The player who proposed sees the dialog too, declining takes the proposal back
+fn answer_proposal(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    view: Res<LevelProposalView>,
+    mut interaction_query: Query<(&Interaction, &mut BackgroundColor, &ProposalAnswer), Changed<Interaction>>,
+    mut link: ServerLink,
+) {
+    if view.proposal.is_none() {
+        return;
+    }
+    let mut answer = None;
+    if keyboard.just_pressed(KeyCode::Enter) {
+        answer = Some(true);
+    }
+    if keyboard.just_pressed(KeyCode::Escape) {
+        answer = Some(false);
+    }
+    for (interaction, mut color, ProposalAnswer(accept)) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *color = PRESSED_BUTTON.into();
+                answer = Some(*accept);
+            }
+            Interaction::Hovered => {
+                *color = HOVERED_BUTTON.into();
+            }
+            Interaction::None => {
+                *color = NORMAL_BUTTON.into();
+            }
+        }
+    }
+    if let Some(accept) = answer {
+        link.send(ClientMessage::LevelResponse { accept });
+    }
+}
+
+fn cleanup_level_select(
+    mut commands: Commands,
+    mut view: ResMut<LevelProposalView>,
+    query: Query<Entity, Or<(With<LevelSelectEntity>, With<ProposalDialog>)>>,
+) {
+    view.proposal = None;
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,563 @@
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    process::{Child, Command},
+};
+
+use bevy::input::{
+    keyboard::{Key, KeyboardInput},
+    ButtonState,
+};
+use renet::RenetClient;
+
+use super::*;
+use crate::networking::{
+    client::{connect_to_server, disconnect_from_server},
+    ClientMessage, DEFAULT_PORT,
+};
+
+pub struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AddressInput>()
+            .init_resource::<RecentServers>()
+            .init_resource::<HostCheats>()
+            .add_systems(Startup, load_recent_servers)
+            .add_systems(OnEnter(GameStates::Lobby), setup_lobby)
+            .add_systems(
+                Update,
+                (
+                    address_input,
+                    update_address_text,
+                    lobby_button_interaction,
+                    recent_server_interaction,
+                    connection_progress,
+                    update_cheats_text,
+                )
+                    .run_if(in_state(GameStates::Lobby)),
+            )
+            .add_systems(OnExit(GameStates::Lobby), cleanup_lobby)
+            .add_systems(Last, shutdown_hosted_server);
+    }
+}
+
+const RECENT_SERVERS_FILE: &str = "recent_servers.ron";
+const MAX_RECENT_SERVERS: usize = 5;
+const CONNECT_TIMEOUT_SECS: f32 = 15.0;
+
+const NORMAL_BUTTON: Color = MY_ORANGE;
+const HOVERED_BUTTON: Color =
+    Color::srgb(222.0 / 255.0 + 0.1, 112.0 / 255.0 + 0.1, 40.0 / 255.0 + 0.1);
+const PRESSED_BUTTON: Color = Color::srgb(0.75, 0.75, 0.75);
+
+#[derive(Component)]
+struct LobbyEntity;
+
+#[derive(Component)]
+struct AddressText;
+
+#[derive(Component)]
+struct LobbyStatus;
+
+#[derive(Component)]
+struct CheatsText;
+
+#[derive(Component, Clone, Copy)]
+enum LobbyButton {
+    Host,
+    Join,
+    Back,
+    Cheats,
+}
+
+#[derive(Component)]
+struct RecentServerButton(String);
+
+// The "address:port" typed by the player
+#[derive(Resource)]
+pub struct AddressInput(pub String);
+
+impl Default for AddressInput {
+    fn default() -> Self {
+        AddressInput("127.0.0.1".to_string())
+    }
+}
+
+// Servers we successfully connected to, the most recent first
+#[derive(Resource, Default, serde::Serialize, serde::Deserialize)]
+pub struct RecentServers(pub Vec<String>);
+
+impl RecentServers {
+    fn remember(&mut self, address: String) {
+        self.0.retain(|a| *a != address);
+        self.0.insert(0, address);
+        self.0.truncate(MAX_RECENT_SERVERS);
+    }
+}
+
+// A connection attempt started from the lobby
+#[derive(Resource)]
+struct PendingConnection {
+    address: String,
+    timer: Timer,
+}
+
+// Whether the server started by "Host" gets --allow-cheats
+#[derive(Resource, Default)]
+struct HostCheats(bool);
+
+fn cheats_label(allow_cheats: bool) -> String {
+    format!("Host cheats: {}", if allow_cheats { "On" } else { "Off" })
+}
+
+// The server process started by the "Host" button
+#[derive(Resource)]
+pub struct HostedServer(pub Child);
+
+fn load_recent_servers(mut recent_servers: ResMut<RecentServers>) {
+    if let Some(saved) = storage::load::<RecentServers>(RECENT_SERVERS_FILE) {
+        *recent_servers = saved;
+    }
+}
+
+fn setup_lobby(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    address_input: Res<AddressInput>,
+    recent_servers: Res<RecentServers>,
+    host_cheats: Res<HostCheats>,
+) {
+    let font = asset_server.load("fonts/NotJamChunky8.ttf");
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::FlexStart,
+                    row_gap: Val::Px(20.0),
+                    padding: UiRect::top(Val::Px(60.0)),
+                    ..default()
+                },
+                ..default()
+            },
+            LobbyEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "MULTIPLAYER",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 60.0,
+                    color: MY_ORANGE,
+                },
+            ));
+
+            parent.spawn(TextBundle::from_section(
+                "Server address (ip:port)",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                },
+            ));
+
+            // Address text field
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(500.0),
+                        height: Val::Px(60.0),
+                        border: UiRect::all(Val::Px(4.0)),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    border_color: BorderColor(MY_ORANGE),
+                    background_color: MY_BROWN.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((
+                        TextBundle::from_section(
+                            format!("{}_", address_input.0),
+                            TextStyle {
+                                font: font.clone(),
+                                font_size: 24.0,
+                                color: Color::WHITE,
+                            },
+                        ),
+                        AddressText,
+                    ));
+                });
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        column_gap: Val::Px(20.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    spawn_lobby_button(parent, &font, "Host", LobbyButton::Host);
+                    spawn_lobby_button(parent, &font, "Join", LobbyButton::Join);
+                    spawn_lobby_button(parent, &font, "Back", LobbyButton::Back);
+                });
+
+            // Level skipping and locked levels on the hosted server
+            parent
+                .spawn((
+                    ButtonBundle {
+                        style: Style {
+                            width: Val::Px(400.0),
+                            height: Val::Px(40.0),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        background_color: NORMAL_BUTTON.into(),
+                        ..default()
+                    },
+                    LobbyButton::Cheats,
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        TextBundle::from_section(
+                            cheats_label(host_cheats.0),
+                            TextStyle {
+                                font: font.clone(),
+                                font_size: 18.0,
+                                color: Color::WHITE,
+                            },
+                        ),
+                        CheatsText,
+                    ));
+                });
+
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 20.0,
+                        color: MY_ORANGE,
+                    },
+                ),
+                LobbyStatus,
+            ));
+
+            if !recent_servers.0.is_empty() {
+                parent.spawn(TextBundle::from_section(
+                    "Recent servers",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 20.0,
+                        color: Color::WHITE,
+                    },
+                ));
+            }
+            for address in recent_servers.0.iter() {
+                parent
+                    .spawn((
+                        ButtonBundle {
+                            style: Style {
+                                width: Val::Px(400.0),
+                                height: Val::Px(40.0),
+                                justify_content: JustifyContent::Center,
+                                align_items: AlignItems::Center,
+                                ..default()
+                            },
+                            background_color: NORMAL_BUTTON.into(),
+                            ..default()
+                        },
+                        RecentServerButton(address.clone()),
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle::from_section(
+                            address.clone(),
+                            TextStyle {
+                                font: font.clone(),
+                                font_size: 18.0,
+                                color: Color::WHITE,
+                            },
+                        ));
+                    });
+            }
+        });
+}
+
+fn spawn_lobby_button(parent: &mut ChildBuilder, font: &Handle<Font>, label: &str, button: LobbyButton) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(160.0),
+                    height: Val::Px(60.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: NORMAL_BUTTON.into(),
+                ..default()
+            },
+            button,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                label,
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 24.0,
+                    color: Color::WHITE,
+                },
+            ));
+        });
+}
+
+fn address_input(
+    mut events: EventReader<KeyboardInput>,
+    mut address_input: ResMut<AddressInput>,
+) {
+    for event in events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Backspace => {
+                address_input.0.pop();
+            }
+            Key::Character(chars) => {
+                for ch in chars.chars() {
+                    if ch.is_ascii_alphanumeric() || ch == '.' || ch == ':' || ch == '-' {
+                        address_input.0.push(ch);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn update_address_text(
+    address_input: Res<AddressInput>,
+    mut query: Query<&mut Text, With<AddressText>>,
+) {
+    if address_input.is_changed() {
+        for mut text in query.iter_mut() {
+            text.sections[0].value = format!("{}_", address_input.0);
+        }
+    }
+}
+
+fn update_cheats_text(host_cheats: Res<HostCheats>, mut query: Query<&mut Text, With<CheatsText>>) {
+    if host_cheats.is_changed() {
+        for mut text in query.iter_mut() {
+            text.sections[0].value = cheats_label(host_cheats.0);
+        }
+    }
+}
+
+fn set_status(status_query: &mut Query<&mut Text, With<LobbyStatus>>, status: String) {
+    for mut text in status_query.iter_mut() {
+        text.sections[0].value = status.clone();
+    }
+}
+
+// "host", "host:port" -> socket address, defaults to DEFAULT_PORT
+fn resolve_address(address: &str) -> Option<SocketAddr> {
+    let address = if address.contains(':') {
+        address.to_string()
+    } else {
+        format!("{}:{}", address, DEFAULT_PORT)
+    };
+    address.to_socket_addrs().ok()?.next()
+}
+
+fn start_connection(
+    commands: &mut Commands,
+    address: &str,
+    status_query: &mut Query<&mut Text, With<LobbyStatus>>,
+) {
+    let Some(server_addr) = resolve_address(address) else {
+        set_status(status_query, format!("Invalid address: {}", address));
+        return;
+    };
+    match connect_to_server(commands, server_addr) {
+        Ok(()) => {
+            commands.insert_resource(PendingConnection {
+                address: address.to_string(),
+                timer: Timer::from_seconds(CONNECT_TIMEOUT_SECS, TimerMode::Once),
+            });
+            set_status(status_query, format!("Connecting to {}...", server_addr));
+        }
+        Err(e) => {
+            set_status(status_query, format!("Failed: {}", e));
+        }
+    }
+}
+
+fn lobby_button_interaction(
+    mut commands: Commands,
+    mut interaction_query: Query<
+        (&Interaction, &mut BackgroundColor, &LobbyButton),
+        Changed<Interaction>,
+    >,
+    mut status_query: Query<&mut Text, With<LobbyStatus>>,
+    mut next_state: ResMut<NextState<GameStates>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    address_input: Res<AddressInput>,
+    pending: Option<Res<PendingConnection>>,
+    hosted_server: Option<Res<HostedServer>>,
+    mut host_cheats: ResMut<HostCheats>,
+) {
+    let mut pressed = None;
+    for (interaction, mut color, button) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *color = PRESSED_BUTTON.into();
+                pressed = Some(*button);
+            }
+            Interaction::Hovered => {
+                *color = HOVERED_BUTTON.into();
+            }
+            Interaction::None => {
+                *color = NORMAL_BUTTON.into();
+            }
+        }
+    }
+    if keyboard.just_pressed(KeyCode::Enter) {
+        pressed = Some(LobbyButton::Join);
+    }
+    if keyboard.just_pressed(KeyCode::Escape) {
+        pressed = Some(LobbyButton::Back);
+    }
+
+    match pressed {
+        Some(LobbyButton::Back) => {
+            if pending.is_some() {
+                commands.remove_resource::<PendingConnection>();
+                disconnect_from_server(&mut commands);
+            }
+            next_state.set(GameStates::GameMenu);
+        }
+        // Only used the next time a server is started
+        Some(LobbyButton::Cheats) => {
+            host_cheats.0 = !host_cheats.0;
+        }
+        // Ignore new attempts while one is in progress
+        Some(_) if pending.is_some() => {}
+        Some(LobbyButton::Join) => {
+            start_connection(&mut commands, &address_input.0, &mut status_query);
+        }
+        Some(LobbyButton::Host) => {
+            if hosted_server.is_none() {
+                let child = std::env::current_exe().and_then(|exe| {
+                    let mut command = Command::new(exe);
+                    command.arg("--server");
+                    if host_cheats.0 {
+                        command.arg("--allow-cheats");
+                    }
+                    command.spawn()
+                });
+                match child {
+                    Ok(child) => {
+                        info!("Started local server process {}", child.id());
+                        commands.insert_resource(HostedServer(child));
+                    }
+                    Err(e) => {
+                        set_status(&mut status_query, format!("Failed to start server: {}", e));
+                        return;
+                    }
+                }
+            }
+            start_connection(&mut commands, "127.0.0.1", &mut status_query);
+        }
+        None => {}
+    }
+}
+
+fn recent_server_interaction(
+    mut interaction_query: Query<
+        (&Interaction, &mut BackgroundColor, &RecentServerButton),
+        Changed<Interaction>,
+    >,
+    mut address_input: ResMut<AddressInput>,
+) {
+    for (interaction, mut color, recent) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *color = PRESSED_BUTTON.into();
+                address_input.0 = recent.0.clone();
+            }
+            Interaction::Hovered => {
+                *color = HOVERED_BUTTON.into();
+            }
+            Interaction::None => {
+                *color = NORMAL_BUTTON.into();
+            }
+        }
+    }
+}
+
+fn connection_progress(
+    mut commands: Commands,
+    time: Res<Time>,
+    client: Option<ResMut<RenetClient>>,
+    pending: Option<ResMut<PendingConnection>>,
+    mut recent_servers: ResMut<RecentServers>,
+    mut status_query: Query<&mut Text, With<LobbyStatus>>,
+) {
+    let (Some(mut client), Some(mut pending)) = (client, pending) else {
+        return;
+    };
+    pending.timer.tick(time.delta());
+
+    if client.is_connected() {
+        info!("Connected to {}", pending.address);
+        recent_servers.remember(pending.address.clone());
+        if let Err(e) = storage::save(RECENT_SERVERS_FILE, &*recent_servers) {
+            warn!("Failed to save recent servers: {}", e);
+        }
+        set_status(&mut status_query, "Connected!".to_string());
+        commands.remove_resource::<PendingConnection>();
+
+        // Same as pressing "Play Game" before: everyone moves on to character selection
+        let message = bincode::serialize(&ClientMessage::StateChangeRequest(
+            GameStates::CharacterSelection,
+        ))
+        .unwrap();
+        client.send_message(0, message);
+    } else if client.is_disconnected() || pending.timer.finished() {
+        let reason = match client.disconnect_reason() {
+            Some(reason) => reason.to_string(),
+            None => "timed out".to_string(),
+        };
+        warn!("Connection to {} failed: {}", pending.address, reason);
+        set_status(&mut status_query, format!("Connection failed: {}", reason));
+        commands.remove_resource::<PendingConnection>();
+        disconnect_from_server(&mut commands);
+    }
+}
+
+fn cleanup_lobby(mut commands: Commands, query: Query<Entity, With<LobbyEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn shutdown_hosted_server(
+    mut exit_events: EventReader<AppExit>,
+    hosted_server: Option<ResMut<HostedServer>>,
+) {
+    if exit_events.read().next().is_some() {
+        if let Some(mut hosted_server) = hosted_server {
+            if let Err(e) = hosted_server.0.kill() {
+                warn!("Failed to stop hosted server: {}", e);
+            }
+        }
+    }
+}
@@ -2,6 +2,10 @@ use bevy::prelude::*;
 use super::*;
 use crate::game::ui::GameHints;
 use crate::networking::{ClientMessage};
+use crate::networking::ai::{AiDifficulty, AiOpponent, AI_PLAYER_ID};
+use crate::networking::{FullGameState, LocalClientMessage, VirtualClientMessage};
+use super::level::CurrentLevelIndex;
+use super::profile::Profile;
 
 pub struct Plugin;
 
@@ -16,14 +20,23 @@ impl bevy::app::Plugin for Plugin {
 #[derive(Component)]
 struct MenuEntity;
 
-#[derive(Component)]
-pub struct PlayButton;
+#[derive(Component, Clone, Copy)]
+pub enum MenuAction {
+    Continue,
+    Multiplayer,
+    LocalGame,
+    SinglePlayer,
+    VersusBot(AiDifficulty),
+    Leaderboards,
+    Replays,
+    Editor,
+}
 
 const NORMAL_BUTTON: Color = MY_ORANGE;
 const HOVERED_BUTTON: Color = Color::srgb(222.0/255.0 + 0.1, 112.0/255.0 + 0.1, 40.0/255.0 + 0.1);
 const PRESSED_BUTTON: Color = Color::srgb(0.75, 0.75, 0.75);
 
-fn setup_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn setup_menu(mut commands: Commands, asset_server: Res<AssetServer>, profile: Res<Profile>) {
     // 游戏标题
     commands.spawn((
         TextBundle::from_section(
@@ -37,7 +50,7 @@ fn setup_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
         .with_text_justify(JustifyText::Center)
         .with_style(Style {
             position_type: PositionType::Absolute,
-            top: Val::Px(150.0),
+            top: Val::Px(100.0),
             left: Val::Px(0.0),
             right: Val::Px(0.0),
             ..default()
@@ -45,71 +58,190 @@ fn setup_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
         MenuEntity,
     ));
 
-    // Play按钮
+    // 按钮列
     commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                top: Val::Px(200.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        },
+        MenuEntity,
+    )).with_children(|parent| {
+        // 有存档时可以从上次的离线对局继续
+        if profile.last_session.is_some() {
+            spawn_menu_button(parent, &asset_server, "Continue", MenuAction::Continue, 400.0);
+        }
+        spawn_menu_button(parent, &asset_server, "Single Player", MenuAction::SinglePlayer, 400.0);
+        spawn_menu_button(parent, &asset_server, "Multiplayer", MenuAction::Multiplayer, 400.0);
+        spawn_menu_button(parent, &asset_server, "Local Game", MenuAction::LocalGame, 400.0);
+        // 人机对战，两个难度并排
+        parent.spawn(NodeBundle {
+            style: Style {
+                column_gap: Val::Px(20.0),
+                ..default()
+            },
+            ..default()
+        }).with_children(|parent| {
+            spawn_menu_button(parent, &asset_server, "Bot Easy", MenuAction::VersusBot(AiDifficulty::Easy), 190.0);
+            spawn_menu_button(parent, &asset_server, "Bot Hard", MenuAction::VersusBot(AiDifficulty::Hard), 190.0);
+        });
+        spawn_menu_button(parent, &asset_server, "Leaderboards", MenuAction::Leaderboards, 400.0);
+        parent.spawn(NodeBundle {
+            style: Style {
+                column_gap: Val::Px(20.0),
+                ..default()
+            },
+            ..default()
+        }).with_children(|parent| {
+            spawn_menu_button(parent, &asset_server, "Replays", MenuAction::Replays, 190.0);
+            spawn_menu_button(parent, &asset_server, "Editor", MenuAction::Editor, 190.0);
+        });
+    });
+}
+
+fn spawn_menu_button(
+    parent: &mut ChildBuilder,
+    asset_server: &Res<AssetServer>,
+    label: &str,
+    action: MenuAction,
+    width: f32,
+) {
+    parent.spawn((
         ButtonBundle {
             style: Style {
-                width: Val::Px(300.0),
-                height: Val::Px(100.0),
+                width: Val::Px(width),
+                height: Val::Px(60.0),
                 justify_content: JustifyContent::Center,
                 align_items: AlignItems::Center,
-                position_type: PositionType::Absolute,
-                left: Val::Percent(50.0),
-                top: Val::Percent(50.0),
-                margin: UiRect {
-                    left: Val::Px(-150.0), //x
-                    top: Val::Px(-50.0),   //y
-                    ..default()
-                },
                 ..default()
             },
             background_color: NORMAL_BUTTON.into(),
             ..default()
         },
-        PlayButton,
-        MenuEntity,
+        action,
     )).with_children(|parent| {
         parent.spawn(TextBundle::from_section(
-            "Play Game",
+            label,
             TextStyle {
                 font: asset_server.load("fonts/NotJamChunky8.ttf"),
-                font_size: 40.0,
+                font_size: 32.0,
                 color: Color::WHITE,
             },
-        )
-    );
+        ));
     });
 }
 
-use renet::RenetClient;
+use renet::{RenetClient, RenetServer};
 fn menu_interaction(
     mut interaction_query: Query<
-        (&Interaction, &mut BackgroundColor),
-        (Changed<Interaction>, With<PlayButton>)
+        (&Interaction, &mut BackgroundColor, &MenuAction),
+        Changed<Interaction>
     >,
+    mut commands: Commands,
     mut next_state: ResMut<NextState<GameStates>>,
+    mut play_mode: ResMut<PlayMode>,
     mut client: Option<ResMut<RenetClient>>,
+    server: Option<Res<RenetServer>>,
+    profile: Res<Profile>,
+    mut current_level: ResMut<CurrentLevelIndex>,
+    mut game_state: ResMut<FullGameState>,
+    mut selected_characters: ResMut<SelectedCharacters>,
+    mut local: EventWriter<LocalClientMessage>,
+    mut virtual_messages: EventWriter<VirtualClientMessage>,
 ) 
 {
-    for (interaction, mut color) in &mut interaction_query {
+    for (interaction, mut color, action) in &mut interaction_query {
         match *interaction {
             Interaction::Pressed => {
                 *color = PRESSED_BUTTON.into();
-// If we're the client, send button press to server
-
-                if let Some(client) = &mut client {
-                    let message = bincode::serialize(&ClientMessage::StateChangeRequest(
-                        GameStates::CharacterSelection
-                    )).unwrap();
-                    info!("send state change to menu->selection");
-                    //next_state.set(GameStates::CharacterSelection);
-                    client.send_message(0, message); // 使用可靠通道
-                } else {
-                    // 如果是服务器，直接处理
-                    info!("handle menu-selection");
-                    next_state.set(GameStates::CharacterSelection);
-                }     
-
+                match action {
+                    MenuAction::Continue => {
+                        let Some(session) = profile.last_session else {
+                            continue;
+                        };
+                        *play_mode = session.play_mode;
+                        match session.opponent {
+                            Some(difficulty) => commands.insert_resource(AiOpponent::new(difficulty)),
+                            None => commands.remove_resource::<AiOpponent>(),
+                        }
+                        current_level.0 = session.level;
+                        // 本地服务器也要从这一关开始
+                        game_state.current_level = CurrentLevelIndex(session.level);
+                        selected_characters.player1 = Some(session.player1);
+                        selected_characters.player2 = Some(session.player2);
+                        // 跳过选人界面，直接开始
+                        for (player_id, character) in [(1, session.player1), (2, session.player2)] {
+                            local.send(LocalClientMessage(ClientMessage::CharacterSelected {
+                                player_id,
+                                character,
+                            }));
+                        }
+                        local.send(LocalClientMessage(ClientMessage::ReadyForGameStart));
+                        // 跳过了选人界面，AI 不会自己准备
+                        if session.opponent.is_some() {
+                            virtual_messages.send(VirtualClientMessage {
+                                player_id: AI_PLAYER_ID,
+                                message: ClientMessage::ReadyForGameStart,
+                            });
+                        }
+                    }
+                    MenuAction::Multiplayer => {
+                        *play_mode = PlayMode::Online;
+                        // 联网时 AI 只在服务器上运行
+                        if server.is_none() {
+                            commands.remove_resource::<AiOpponent>();
+                        }
+                        // If we're the client, send button press to server
+                        if let Some(client) = &mut client {
+                            let message = bincode::serialize(&ClientMessage::StateChangeRequest(
+                                GameStates::CharacterSelection
+                            )).unwrap();
+                            info!("send state change to menu->selection");
+                            client.send_message(0, message); // 使用可靠通道
+                        } else if server.is_some() {
+                            // 如果是服务器，直接处理
+                            info!("handle menu-selection");
+                            next_state.set(GameStates::CharacterSelection);
+                        } else {
+                            // 还没有连接，去选择服务器
+                            next_state.set(GameStates::Lobby);
+                        }
+                    }
+                    MenuAction::LocalGame => {
+                        // 两个人用同一个键盘，不需要服务器
+                        *play_mode = PlayMode::Local;
+                        commands.remove_resource::<AiOpponent>();
+                        next_state.set(GameStates::CharacterSelection);
+                    }
+                    MenuAction::SinglePlayer => {
+                        // 一个人控制所有的鸭子
+                        *play_mode = PlayMode::Solo;
+                        commands.remove_resource::<AiOpponent>();
+                        next_state.set(GameStates::CharacterSelection);
+                    }
+                    MenuAction::VersusBot(difficulty) => {
+                        // 本地服务器上的 AI 控制 Player2
+                        *play_mode = PlayMode::Local;
+                        commands.insert_resource(AiOpponent::new(*difficulty));
+                        next_state.set(GameStates::CharacterSelection);
+                    }
+                    MenuAction::Leaderboards => {
+                        next_state.set(GameStates::Leaderboard);
+                    }
+                    MenuAction::Replays => {
+                        next_state.set(GameStates::Replays);
+                    }
+                    MenuAction::Editor => {
+                        next_state.set(GameStates::Editor);
+                    }
+                }
             }
             
             Interaction::Hovered => {
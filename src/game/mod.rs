@@ -12,6 +12,15 @@ pub mod utils;
 pub mod menu;
 pub mod selection; 
 pub mod celebration;
+pub mod lobby;
+pub mod storage;
+pub mod progress;
+pub mod profile;
+pub mod leaderboard;
+pub mod replay;
+pub mod level_select;
+pub mod editor;
+pub mod character;
 
 use utils::*;
 
@@ -36,6 +45,7 @@ impl bevy::app::Plugin for Plugin {
                     .load_collection::<ImageAssets>(),
             )
             .init_resource::<SelectedCharacters>()
+            .init_resource::<PlayMode>()
             .add_plugins((
                 player::Plugin,
                 audio::Plugin,
@@ -45,7 +55,16 @@ impl bevy::app::Plugin for Plugin {
                 menu::Plugin,
                 selection::Plugin,
                 celebration::Plugin,
+                lobby::Plugin,
+                progress::Plugin,
+                profile::Plugin,
+                leaderboard::Plugin,
+                replay::Plugin,
+                level_select::Plugin,
+                editor::Plugin,
             ))
+            // add_plugins 一次最多 15 个
+            .add_plugins(character::Plugin)
             .add_systems(Startup, spawn_camera);
 
     }
@@ -85,6 +104,7 @@ pub struct ImageAssets {
     bread: Handle<Image>,
     #[asset(path = "sprites/breaking_ice.png")]
     breaking_ice: Handle<Image>,
+    // 选关缩略图里的鸭子，游戏里的角色贴图在 character::Characters 里
     #[asset(path = "sprites/duck.png")]
     duck: Handle<Image>,
     #[asset(path = "sprites/ice.png")]
@@ -95,30 +115,29 @@ pub struct ImageAssets {
     wall: Handle<Image>,
     #[asset(path = "sprites/water.png")]
     water: Handle<Image>,
-    #[asset(path = "sprites/cat.png")]
-    cat: Handle<Image>,
-    #[asset(path = "sprites/stuffed_cat.png")]
-    stuffed_cat: Handle<Image>,
-    #[asset(path = "sprites/bunny.png")]
-    bunny: Handle<Image>,
-    #[asset(path = "sprites/stuffed_bunny.png")]
-    stuffed_bunny: Handle<Image>,
-    #[asset(path = "sprites/chick.png")]
-    chick: Handle<Image>,
-    #[asset(path = "sprites/stuffed_chick.png")]
-    stuffed_chick: Handle<Image>,
+    #[asset(path = "sprites/arrow_tile.png")]
+    arrow_tile: Handle<Image>,
+    #[asset(path = "sprites/portal.png")]
+    portal: Handle<Image>,
+    #[asset(path = "sprites/ice_block.png")]
+    ice_block: Handle<Image>,
+    #[asset(path = "sprites/plate.png")]
+    plate: Handle<Image>,
+    #[asset(path = "sprites/toggle_plate.png")]
+    toggle_plate: Handle<Image>,
+    #[asset(path = "sprites/gate.png")]
+    gate: Handle<Image>,
+    #[asset(path = "sprites/cracked_ice.png")]
+    cracked_ice: Handle<Image>,
+    #[asset(path = "sprites/sturdy_ice.png")]
+    sturdy_ice: Handle<Image>,
+    #[asset(path = "sprites/pepper.png")]
+    pepper: Handle<Image>,
 
 }
 
-// 添加角色类型
-#[derive(Component, Default,Clone, Copy, PartialEq,serde::Deserialize,serde::Serialize, Eq, Debug)]
-pub enum CharacterType {
-    #[default]
-    Duck,
-    Cat,
-    Chick,
-    Bunny,
-}
+// 角色类型来自 assets/characters.ron
+pub use character::CharacterType;
 
 //player choose
 #[derive(Resource, Default,serde::Serialize,serde::Deserialize)]
@@ -127,12 +146,34 @@ pub struct SelectedCharacters {
     pub player1: Option<CharacterType>,
     pub player2: Option<CharacterType>,
 }
+// 联网对战，两个人在同一台电脑上离线游戏，或者一个人解谜
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+pub enum PlayMode {
+    #[default]
+    Online,
+    Local,
+    Solo,
+    /// 播放录像，不接受输入
+    Replay,
+}
+
+impl PlayMode {
+    pub fn is_offline(self) -> bool {
+        matches!(self, PlayMode::Local | PlayMode::Solo)
+    }
+}
+
 #[derive(Clone, Eq, Copy,PartialEq, Debug, Hash, Default, States,serde::Serialize,serde::Deserialize)]
 pub enum GameStates {
     #[default]
     Loading,
     GameMenu,
+    Lobby,
     CharacterSelection,
     Next,
     Celebration,
+    Leaderboard,
+    Replays,
+    LevelSelect,
+    Editor,
 }
@@ -215,7 +215,7 @@ fn server_event_system(
                 // 通知客户端他们的玩家类型
                 let message = NetworkMessage::PlayerSelection {
                     player: player_type,
-                    character: CharacterType::Duck, // 默认角色
+                    character: CharacterType::default(), // 默认角色
                 };
                 
                 server.send_message(
@@ -1,10 +1,13 @@
 use super::{
     audio::PlaySFX,
+    character::{Ability, Characters},
+    cursor::CharacterTag,
     level::{get_entity_on_logic_position, SymbolType::*, UpdateLevel},
+    progress::MoveCounter,
     *,
 };
 use bevy::utils::Duration;
-use crate::networking::ClientMessage;
+use crate::networking::{ClientMessage, ServerLink};
 pub struct Plugin;
 
 impl bevy::app::Plugin for Plugin {
@@ -17,11 +20,14 @@ impl bevy::app::Plugin for Plugin {
                 component_animator_system::<Transform>,
                 shake_other_ducks_in_direction,
                 handle_remote_player_move,
+                animate_pushed_blocks,
             )
                 .run_if(in_state(GameStates::Next)),
             
         )
         .add_event::<RemotePlayerMove>()
+        .add_event::<MoveApplied>()
+        .init_resource::<PushedBlocks>()
         .add_event::<ShakeOtherDucksInDir>();
     }
 }
@@ -33,15 +39,52 @@ pub trait Duck {
     //fn is_stuffed(&self) -> bool;
     fn set_logic_position(&mut self, position: (usize, usize));
     fn set_can_move(&mut self, can_move: bool);
-    fn eat_bread(&mut self);
+    fn eat_bread(&mut self, points: u32);
+    fn is_full(&self) -> bool;//吃饱了就不能再吃面包
+    fn is_heavy(&self) -> bool;//吃过沉面包
+    fn set_heavy(&mut self, heavy: bool);
+    fn get_peppers(&self) -> u32;//还没用掉的辣椒
+    fn set_peppers(&mut self, peppers: u32);
+    fn can_use_ability(&self, ability: Ability) -> bool;//有这个能力并且这一关还没用完
+    fn use_ability(&mut self);
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct CommonDuck {
     pub logic_position: (usize, usize),
     pub can_move: bool, // stuffed_duck on breaking_ice => can't move
     pub bread_count: u32,
-    //pub belly_capacity: u32,
+    pub loaves: u32,
+    pub belly_capacity: u32,
+    pub heavy: bool,
+    pub peppers: u32,
+    pub ability: Ability,
+    pub ability_uses: u32,
+    /// 属于哪个玩家，NEUTRAL 表示没有主人
+    pub owner: u8,
+}
+
+/// 没有主人的鸭子，对战时谁都不能操作
+pub const NEUTRAL: u8 = 0;
+
+/// 单人模式可以控制所有小动物，对战时只能控制属于自己的
+pub fn can_select(duck: &CommonDuck, player_id: u8, play_mode: PlayMode) -> bool {
+    play_mode == PlayMode::Solo || duck.owner == player_id
+}
+
+impl CommonDuck {
+    /// active：是不是主人正在操作的那只
+    pub fn record(&self, active: bool) -> level::DuckRecord {
+        level::DuckRecord {
+            bread_count: self.bread_count,
+            loaves: self.loaves,
+            heavy: self.heavy,
+            peppers: self.peppers,
+            ability_uses: self.ability_uses,
+            owner: self.owner,
+            active,
+        }
+    }
 }
 
 impl Duck for CommonDuck {
@@ -65,8 +108,40 @@ impl Duck for CommonDuck {
         self.can_move = can_move;
     }
 
-    fn eat_bread(&mut self) {
-        self.bread_count += 1;
+    fn eat_bread(&mut self, points: u32) {
+        self.bread_count += points;
+        self.loaves += 1;
+    }
+
+    fn is_full(&self) -> bool {
+        self.loaves >= self.belly_capacity
+    }
+
+    fn is_heavy(&self) -> bool {
+        self.heavy
+    }
+
+    fn set_heavy(&mut self, heavy: bool) {
+        self.heavy = heavy;
+    }
+
+    fn get_peppers(&self) -> u32 {
+        self.peppers
+    }
+
+    fn set_peppers(&mut self, peppers: u32) {
+        self.peppers = peppers;
+    }
+
+    fn can_use_ability(&self, ability: Ability) -> bool {
+        self.ability == ability && self.ability_uses > 0
+    }
+
+    fn use_ability(&mut self) {
+        // 不限次数的能力一直是 u32::MAX
+        if self.ability_uses != u32::MAX {
+            self.ability_uses = self.ability_uses.saturating_sub(1);
+        }
     }
 
     //fn is_stuffed(&self) -> bool {
@@ -79,7 +154,6 @@ impl Duck for CommonDuck {
 pub struct Player1;
 #[derive(Component)]
 pub struct Player2;
-use renet::RenetClient;
 
 /* 
 //player movement
@@ -390,7 +464,7 @@ fn player2_movement(
 
 fn player1_movement(
     key_board_input: Res<ButtonInput<KeyCode>>,
-    mut client: Option<ResMut<RenetClient>>,
+    mut link: ServerLink,
 ) {
     let mut direction = utils::Direction::None;
 
@@ -408,21 +482,19 @@ fn player1_movement(
     }
 
     if let Some(dir) = Some(direction).filter(|d| *d != utils::Direction::None) {
-        if let Some(client) = &mut client {
-            let message = bincode::serialize(&ClientMessage::PlayerMovementInput {
-                player_id: 1,
-                direction: dir,
-            }).unwrap();
-            client.send_message(0, message);
-            info!("Sent Player1 input: {:?}", dir);
-        }
+        link.send(ClientMessage::PlayerMovementInput {
+            player_id: 1,
+            direction: dir,
+            use_ability: key_board_input.pressed(KeyCode::ShiftLeft),
+        });
+        info!("Sent Player1 input: {:?}", dir);
     }
 }
 
 
 fn player2_movement(
     key_board_input: Res<ButtonInput<KeyCode>>,
-    mut client: Option<ResMut<RenetClient>>,
+    mut link: ServerLink,
 ) {
     let mut direction = utils::Direction::None;
 
@@ -440,108 +512,318 @@ fn player2_movement(
     }
 
     if let Some(dir) = Some(direction).filter(|d| *d != utils::Direction::None) {
-        if let Some(client) = &mut client {
-            let message = bincode::serialize(&ClientMessage::PlayerMovementInput {
-                player_id: 2,
-                direction: dir,
-            }).unwrap();
-            client.send_message(0, message);
-            info!("Sent Player2 input: {:?}", dir);
+        link.send(ClientMessage::PlayerMovementInput {
+            player_id: 2,
+            direction: dir,
+            use_ability: key_board_input.pressed(KeyCode::ShiftRight),
+        });
+        info!("Sent Player2 input: {:?}", dir);
+    }
+}
+
+
+
+// 传送时缩小再放大的时间
+const TELEPORT_MILI_SECS: u64 = 240;
+
+/// 滑行路线上的一个点
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waypoint {
+    /// 滑到这个格子（拐弯处或终点）
+    Slide((usize, usize)),
+    /// 从传送门的另一头出来
+    Teleport((usize, usize)),
+}
+
+impl Waypoint {
+    pub fn position(self) -> (usize, usize) {
+        match self {
+            Waypoint::Slide(position) | Waypoint::Teleport(position) => position,
         }
     }
 }
 
+/// 被鸭子撞走的冰块
+#[derive(Clone, Copy, Debug)]
+pub struct BlockPush {
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    /// 冰块掉进水里，把水填成了冰面
+    pub filled_water: bool,
+}
+
+/// 一次滑行经过的路线
+pub struct SlipPath {
+    /// 依次经过的点，最后一个是终点
+    pub waypoints: Vec<Waypoint>,
+    pub pushed: Option<BlockPush>,
+}
+
+impl SlipPath {
+    pub fn end(&self) -> (usize, usize) {
+        self.waypoints.last().unwrap().position()
+    }
+
+    /// 鸭子原地不动但推走了冰块也算一步
+    pub fn changed_level(&self, from: (usize, usize)) -> bool {
+        self.end() != from || self.pushed.is_some()
+    }
+}
+
+// Up: row--, Down: row++, Left: col--, Right: col++
+fn step(level: &level::Level, position: (usize, usize), direction: utils::Direction) -> Option<(usize, usize)> {
+    let next = match direction {
+        utils::Direction::Up => (position.0.checked_sub(1)?, position.1),
+        utils::Direction::Down => (position.0 + 1, position.1),
+        utils::Direction::Left => (position.0, position.1.checked_sub(1)?),
+        utils::Direction::Right => (position.0, position.1 + 1),
+        utils::Direction::None => return None,
+    };
+    level.0.get(next.0)?.get(next.1)?;
+    Some(next)
+}
+
+// 箭头和传送门上不能停留，鸭子滑进去以后从别处、朝别的方向离开
+enum Passage {
+    Normal,
+    Blocked,
+    Through((usize, usize), utils::Direction),
+}
+
+fn passage(level: &level::Level, position: (usize, usize), direction: utils::Direction) -> Passage {
+    match level::SymbolType::from_symbol(level.0[position.0][position.1]) {
+        Some(level::SymbolType::Portal(_)) => match level::portal_twin(&level.0, position) {
+            Some(twin) => Passage::Through(twin, direction),
+            None => Passage::Blocked,
+        },
+        Some(symbol) => match symbol.arrow_direction() {
+            // 不能逆着箭头进去
+            Some(arrow) if arrow == direction.opposite() => Passage::Blocked,
+            Some(arrow) => Passage::Through(position, arrow),
+            None => Passage::Normal,
+        },
+        None => Passage::Normal,
+    }
+}
+
+// 离开箭头或传送门后的下一个格子进不去时，连它们本身也进不去
+fn can_enter(
+    level: &level::Level,
+    position: (usize, usize),
+    direction: utils::Direction,
+    duck: &dyn Duck,
+    depth: usize,
+) -> bool {
+    if !is_valid_move(level.0[position.0][position.1], duck) {
+        return false;
+    }
+    match passage(level, position, direction) {
+        Passage::Normal => true,
+        Passage::Blocked => false,
+        Passage::Through(exit, exit_direction) => {
+            depth > 0
+                && step(level, exit, exit_direction)
+                    .is_some_and(|next| can_enter(level, next, exit_direction, duck, depth - 1))
+        }
+    }
+}
 
+// 冰块沿着冰面滑到撞上东西为止，滑进水里就把水填平。一格都动不了时和墙一样
+fn push_block(level: &mut level::Level, from: (usize, usize), direction: utils::Direction) -> Option<BlockPush> {
+    let mut position = from;
+    let mut filled_water = false;
+    while let Some(next) = step(level, position, direction) {
+        let symbol = level.0[next.0][next.1];
+        if symbol == Ice.get_symbol() {
+            position = next;
+        } else {
+            if symbol == BrokenIce.get_symbol() {
+                position = next;
+                filled_water = true;
+            }
+            break;
+        }
+    }
+    if position == from {
+        return None;
+    }
+    level.0[from.0][from.1] = Ice.get_symbol();
+    level.0[position.0][position.1] = if filled_water {
+        Ice.get_symbol()
+    } else {
+        IceBlock.get_symbol()
+    };
+    Some(BlockPush {
+        from,
+        to: position,
+        filled_water,
+    })
+}
 
-// Slip until hitting the wall or bread
+// Slip until hitting the wall or bread, arrows and portals send the duck on
 // common duck
 pub fn slip(
     duck: &mut dyn Duck,
     direction: utils::Direction,
-    // resource
-    mut level: ResMut<level::Level>,
-) -> (usize, usize) {
-    // Up: row--, Down: row++, Left: col--, Right: col++
-    let rows = level.0.len();
+    level: &mut level::Level,
+    switches: &level::Switches,
+    use_ability: bool,
+) -> SlipPath {
     let logic_position = duck.get_logic_position();
-    let cols = level.0[logic_position.0].len();
+    let cells = level.0.iter().map(|row| row.len()).sum();
     let mut position = logic_position;
-    match direction {
-        utils::Direction::Up => {
-            while position.0 > 0 && is_valid_move(level.0[position.0 - 1][position.1], duck) {
-                position.0 -= 1;
-                if collide_with_object(level.0[position.0][position.1], duck) {
-                    break;
-                }
-            }
-        }
-        utils::Direction::Down => {
-            while position.0 < rows - 1 && is_valid_move(level.0[position.0 + 1][position.1], duck)
+    let mut direction = direction;
+    let mut waypoints = Vec::new();
+    // 经过的箭头和传送门，再遇到说明在绕圈
+    let mut passed = Vec::new();
+    // 最后一个能停下的格子，以及那时的路线长度
+    let mut rest = (position, 0);
+    let mut pushed = None;
+    // 出发时就吃过沉面包的鸭子，滑过的冰面都会被压裂
+    let heavy = duck.is_heavy();
+    let mut trail = Vec::new();
+    // 主动能力只在出发时决定用不用
+    let mut hop = use_ability && duck.can_use_ability(Ability::Hop);
+    let stop_early = use_ability && duck.can_use_ability(Ability::StopEarly);
+    while let Some(mut next) = step(level, position, direction) {
+        // 兔子跳过旁边的一格，落地的格子进不去时照常滑
+        if std::mem::take(&mut hop) {
+            if let Some(landing) = step(level, next, direction)
+                .filter(|&landing| can_enter(level, landing, direction, duck, cells))
             {
-                position.0 += 1;
-                if collide_with_object(level.0[position.0][position.1], duck) {
-                    break;
-                }
+                duck.use_ability();
+                next = landing;
             }
         }
-        utils::Direction::Left => {
-            while position.1 > 0 && is_valid_move(level.0[position.0][position.1 - 1], duck) {
-                position.1 -= 1;
-                if collide_with_object(level.0[position.0][position.1], duck) {
-                    break;
+        // 鸭子踩碎要碎的冰以后游过去，冰变成水；对岸进不去时停在冰前面，不会掉下去
+        if level.0[next.0][next.1] == BreakingIce.get_symbol() && duck.can_use_ability(Ability::Swim) {
+            match step(level, next, direction) {
+                Some(shore) if can_enter(level, shore, direction, duck, cells) => {
+                    duck.use_ability();
+                    level.0[next.0][next.1] = BrokenIce.get_symbol();
+                    position = next;
+                    continue;
                 }
+                _ => break,
             }
         }
-        utils::Direction::Right => {
-            while position.1 < cols - 1 && is_valid_move(level.0[position.0][position.1 + 1], duck)
-            {
-                position.1 += 1;
-                if collide_with_object(level.0[position.0][position.1], duck) {
-                    break;
-                }
+        // 撞上冰块时鸭子停下，冰块自己滑走
+        if level.0[next.0][next.1] == IceBlock.get_symbol() {
+            pushed = push_block(level, next, direction);
+            break;
+        }
+        if !can_enter(level, next, direction, duck, cells) {
+            break;
+        }
+        if let Passage::Through(exit, exit_direction) = passage(level, next, direction) {
+            if passed.contains(&next) {
+                // 绕圈时停在最后一个能停下的格子
+                position = rest.0;
+                waypoints.truncate(rest.1);
+                break;
+            }
+            passed.push(next);
+            if exit != next {
+                waypoints.push(Waypoint::Slide(next));
+                waypoints.push(Waypoint::Teleport(exit));
+            } else if exit_direction != direction {
+                waypoints.push(Waypoint::Slide(next));
             }
+            position = exit;
+            direction = exit_direction;
+            continue;
+        }
+        position = next;
+        rest = (position, waypoints.len());
+        let symbol = level.0[position.0][position.1];
+        // 有裂纹的冰经过一次就裂得更厉害
+        if let Some(cracked) = level::SymbolType::from_symbol(symbol).and_then(level::SymbolType::crack) {
+            level.0[position.0][position.1] = cracked.get_symbol();
+        }
+        if collide_with_object(symbol, duck) {
+            break;
+        }
+        // 没停下来的话，吃掉的面包和捡到的辣椒都不在了
+        if level::SymbolType::from_symbol(symbol).is_some_and(|symbol| symbol.is_bread() || symbol == Pepper) {
+            level.0[position.0][position.1] = Ice.get_symbol();
+        }
+        trail.push(position);
+        // 猫滑一格就停下
+        if stop_early {
+            duck.use_ability();
+            break;
         }
-        _ => (),
     }
+    waypoints.push(Waypoint::Slide(position));
 
     // Update symbols on the level
     let mut duck_char: char = DuckOnIce.get_symbol();
-    if duck.get_bread_count() > 0 {
+    if duck.is_full() {
         duck_char = StuffedDuckOnIce.get_symbol();
     }
 
-    if level.0[logic_position.0][logic_position.1] == DuckOnBreakingIce.get_symbol() {
+    let from_symbol = level.0[logic_position.0][logic_position.1];
+    if from_symbol == DuckOnThinIce.get_symbol() || from_symbol == DuckOnBreakingIce.get_symbol() {
         level.0[logic_position.0][logic_position.1] = BreakingIce.get_symbol();
+    } else if from_symbol == DuckOnCrackedIce.get_symbol() {
+        level.0[logic_position.0][logic_position.1] = CrackedIce.get_symbol();
     } else {
         level.0[logic_position.0][logic_position.1] = Ice.get_symbol();
     }
+    // 停在要碎的冰上还能动（冰刚被压到 * 或者是小鸡），掉下去的下面会改成 P
     if level.0[position.0][position.1] == BreakingIce.get_symbol() {
-        level.0[position.0][position.1] = DuckOnBreakingIce.get_symbol();
+        level.0[position.0][position.1] = DuckOnThinIce.get_symbol();
+    } else if level.0[position.0][position.1] == CrackedIce.get_symbol() {
+        level.0[position.0][position.1] = DuckOnCrackedIce.get_symbol();
     } else {
         level.0[position.0][position.1] = duck_char;
     }
     if !duck.can_move() {
         level.0[position.0][position.1] = DuckOnWater.get_symbol();
     }
-    position
+    // 闸门在每次移动以后重新计算
+    switches.update(level, logic_position, position);
+    if heavy && position != logic_position {
+        for cell in trail.into_iter().chain([logic_position]) {
+            if cell != position && level.0[cell.0][cell.1] == Ice.get_symbol() {
+                level.0[cell.0][cell.1] = BreakingIce.get_symbol();
+            }
+        }
+    }
+    SlipPath { waypoints, pushed }
 }
 
 fn is_valid_move(symbol: char, duck: &dyn Duck) -> bool {
     symbol != Wall.get_symbol()
-        && symbol != DuckOnIce.get_symbol()
-        && symbol != DuckOnWater.get_symbol()
-        && symbol != DuckOnBreakingIce.get_symbol()
-        && symbol != StuffedDuckOnIce.get_symbol()
-        && (!duck.get_bread_count()>0 || symbol != BreadOnIce.get_symbol())
+        && !level::SymbolType::from_symbol(symbol).is_some_and(level::SymbolType::is_duck)
+        && symbol != IceBlock.get_symbol()
+        && symbol != ClosedGate.get_symbol()
+        && !(duck.is_full() && level::SymbolType::from_symbol(symbol).is_some_and(level::SymbolType::is_bread))
 }
 
 // TODO: replace it with eat_bread_or_break_ice
 fn collide_with_object(symbol: char, duck: &mut dyn Duck) -> bool {
-    if symbol == BreadOnIce.get_symbol() {
-        duck.eat_bread();
+    if symbol == Pepper.get_symbol() {
+        duck.set_peppers(duck.get_peppers() + 1);
+        return false;
+    }
+    if let Some(bread) = level::SymbolType::from_symbol(symbol).filter(|symbol| symbol.is_bread()) {
+        duck.eat_bread(bread.bread_points());
+        if bread == HeavyBread {
+            duck.set_heavy(true);
+        }
+        // 有辣椒的话吃完接着滑
+        if duck.get_peppers() > 0 {
+            duck.set_peppers(duck.get_peppers() - 1);
+            return false;
+        }
         return true;
     }
     if symbol == BreakingIce.get_symbol() {
+        // 小鸡很轻，踩上去也不会掉下去
+        if duck.can_use_ability(Ability::LightFeet) {
+            return false;
+        }
         duck.set_can_move(false);
         return true;
     }
@@ -579,14 +861,7 @@ pub fn shake_other_ducks_in_direction(
             position.0 = (delta.0 + position.0 as i32) as usize;
             position.1 = (delta.1 + position.1 as i32) as usize;
             let symbol = level.0[position.0][position.1];
-            if [
-                DuckOnBreakingIce.get_symbol(),
-                DuckOnIce.get_symbol(),
-                DuckOnWater.get_symbol(),
-                StuffedDuckOnIce.get_symbol(),
-            ]
-            .contains(&symbol)
-            {
+            if level::SymbolType::from_symbol(symbol).is_some_and(level::SymbolType::is_duck) {
                 if let Some(entity) = get_entity_on_logic_position(position, &query) {
                     ducks_to_shake.push(entity);
                 }
@@ -616,6 +891,16 @@ pub fn shake_other_ducks_in_direction(
 }
 
 use crate::networking::RemotePlayerMove;
+
+/// 一次真正让鸭子滑动了的移动，from 是出发的格子
+#[derive(Event, Clone, Copy, Debug)]
+pub struct MoveApplied {
+    pub player_id: u8,
+    pub direction: utils::Direction,
+    pub from: (usize, usize),
+    pub use_ability: bool,
+}
+
 pub fn handle_remote_player_move(
     mut commands: Commands,
     mut move_events: EventReader<RemotePlayerMove>,
@@ -624,6 +909,7 @@ pub fn handle_remote_player_move(
         &mut Sprite,
         &mut Handle<Image>,
         Option<&mut CommonDuck>,
+        &CharacterTag,
         Entity,
     ), (With<Player1>,Without<Player2>)>,
     mut player2_query: Query<(
@@ -631,25 +917,29 @@ pub fn handle_remote_player_move(
         &mut Sprite,
         &mut Handle<Image>,
         Option<&mut CommonDuck>,
+        &CharacterTag,
         Entity,
     ), (With<Player2>,Without<Player1>)>,
     mut events_sfx: EventWriter<PlaySFX>,
     mut events_update: EventWriter<UpdateLevel>,
     mut event_shake: EventWriter<ShakeOtherDucksInDir>,
     mut events_print: EventWriter<level::PrintLevel>,
-    level: ResMut<level::Level>,
-    asset_server: Res<AssetServer>,
+    mut level: ResMut<level::Level>,
+    characters: Res<Characters>,
     audio_assets: Res<AudioAssets>,
-    selected_characters: Res<SelectedCharacters>,
+    mut move_counter: ResMut<MoveCounter>,
+    mut moves_applied: EventWriter<MoveApplied>,
+    mut pushed_blocks: ResMut<PushedBlocks>,
+    switches: Res<level::Switches>,
 ) {
-    if let Some(RemotePlayerMove { player_id, direction }) = move_events.read().next() {
+    if let Some(RemotePlayerMove { player_id, direction, use_ability }) = move_events.read().next() {
         let query = match player_id {
             1 => player1_query.get_single_mut(),
             2 => player2_query.get_single_mut(),
             _ => return,
         };
 
-        if let Ok((transform, mut sprite, mut image, c_duck, entity)) = query {
+        if let Ok((transform, mut sprite, mut image, c_duck, character_tag, entity)) = query {
             let duck: &mut dyn Duck = c_duck.unwrap().into_inner();
 
             if !duck.can_move() {
@@ -666,9 +956,11 @@ pub fn handle_remote_player_move(
             let before = duck.get_bread_count();
             let before_move = duck.can_move();
 
-            let end_position = slip(duck, *direction, level);
+            let path = slip(duck, *direction, &mut level, &switches, *use_ability);
+            let end_position = path.end();
             let after = duck.get_bread_count();
             let after_move = duck.can_move();
+            sprite.color = level::duck_tint(duck.is_heavy());
 
             if after > before {
                 events_sfx.send(PlaySFX {
@@ -677,23 +969,9 @@ pub fn handle_remote_player_move(
                 });
             }
 
-            if after > 0 {
-                let texture_handle = match player_id {
-                    1 => match selected_characters.player1.unwrap_or(CharacterType::Duck) {
-                        CharacterType::Duck => asset_server.load("sprites/stuffed_duck.png"),
-                        CharacterType::Cat => asset_server.load("sprites/stuffed_cat.png"),
-                        CharacterType::Bunny => asset_server.load("sprites/stuffed_bunny.png"),
-                        CharacterType::Chick => asset_server.load("sprites/stuffed_chick.png"),
-                    },
-                    2 => match selected_characters.player2.unwrap_or(CharacterType::Duck) {
-                        CharacterType::Duck => asset_server.load("sprites/stuffed_duck.png"),
-                        CharacterType::Cat => asset_server.load("sprites/stuffed_cat.png"),
-                        CharacterType::Bunny => asset_server.load("sprites/stuffed_bunny.png"),
-                        CharacterType::Chick => asset_server.load("sprites/stuffed_chick.png"),
-                    },
-                    _=> return,
-                };
-                *image = texture_handle;
+            if duck.is_full() {
+                // The controlled duck may change in single player, so use the duck's own character
+                *image = characters.sprite(character_tag.0, true);
             }
 
             if before_move && !after_move {
@@ -703,18 +981,20 @@ pub fn handle_remote_player_move(
                 });
             }
 
+            if let Some(push) = path.pushed.filter(|push| !push.filled_water) {
+                pushed_blocks.0.push(push);
+            }
+            if path.changed_level(duck.get_logic_position()) {
+                move_counter.add(*player_id);
+                moves_applied.send(MoveApplied {
+                    player_id: *player_id,
+                    direction: *direction,
+                    from: duck.get_logic_position(),
+                    use_ability: *use_ability,
+                });
+            }
+            let tween_translation = slide_tween(transform.translation, duck.get_logic_position(), &path);
             duck.set_logic_position(end_position);
-            let v3 = logic_position_to_translation(end_position);
-
-            let tween_translation = Tween::new(
-                EaseFunction::QuadraticInOut,
-                Duration::from_millis(DUCK_MOVE_MILI_SECS),
-                TransformPositionLens {
-                    start: transform.translation,
-                    end: Vec3::new(v3.x, v3.y, 1.0),
-                },
-            )
-            .with_repeat_count(1);
 
             let origin_scale = Vec3::new(1.0 * RESIZE, 1.0 * RESIZE, 1.0);
             let new_scale = transform.scale * Vec3::new(1.3, 0.7, 1.);
@@ -728,7 +1008,9 @@ pub fn handle_remote_player_move(
             )
             .with_repeat_count(1);
 
-            let track: Tracks<Transform> = Tracks::new(vec![tween_translation, tween_scale]);
+            // 路线放在后面，传送时的缩放盖过落地的压扁
+            let tweens: Vec<BoxedTweenable<Transform>> = vec![tween_scale.into(), tween_translation.into()];
+            let track: Tracks<Transform> = Tracks::new(tweens);
             commands.entity(entity).insert(Animator::new(track));
 
             event_shake.send(ShakeOtherDucksInDir {
@@ -745,3 +1027,372 @@ pub fn handle_remote_player_move(
         }
     }
 }
+
+// 沿着路线依次滑过去，总时长和直线滑行一样；传送时在入口缩小，在出口放大
+fn slide_tween(start: Vec3, from: (usize, usize), path: &SlipPath) -> Sequence<Transform> {
+    let distance = |a: (usize, usize), b: (usize, usize)| (a.0.abs_diff(b.0) + a.1.abs_diff(b.1)) as u64;
+    let mut total = 0;
+    let mut previous = from;
+    for waypoint in &path.waypoints {
+        if let Waypoint::Slide(position) = waypoint {
+            total += distance(previous, *position);
+        }
+        previous = waypoint.position();
+    }
+    let total = total.max(1);
+
+    let origin_scale = Vec3::new(1.0 * RESIZE, 1.0 * RESIZE, 1.0);
+    let vanished_scale = Vec3::new(0.0, 0.0, 1.0);
+    let half_teleport = Duration::from_millis(TELEPORT_MILI_SECS / 2);
+    let mut tweens: Vec<BoxedTweenable<Transform>> = Vec::new();
+    let mut start = start;
+    let mut previous = from;
+    for waypoint in &path.waypoints {
+        let v3 = logic_position_to_translation(waypoint.position());
+        let end = Vec3::new(v3.x, v3.y, 1.0);
+        match waypoint {
+            Waypoint::Slide(position) => {
+                let millis = (DUCK_MOVE_MILI_SECS * distance(previous, *position) / total).max(1);
+                tweens.push(
+                    Tween::new(
+                        EaseFunction::QuadraticInOut,
+                        Duration::from_millis(millis),
+                        TransformPositionLens { start, end },
+                    )
+                    .into(),
+                );
+            }
+            Waypoint::Teleport(_) => {
+                tweens.push(
+                    Tween::new(
+                        EaseFunction::QuadraticIn,
+                        half_teleport,
+                        TransformScaleLens {
+                            start: origin_scale,
+                            end: vanished_scale,
+                        },
+                    )
+                    .into(),
+                );
+                tweens.push(
+                    Tween::new(
+                        EaseFunction::QuadraticInOut,
+                        Duration::from_millis(1),
+                        TransformPositionLens { start, end },
+                    )
+                    .into(),
+                );
+                tweens.push(
+                    Tween::new(
+                        EaseFunction::QuadraticOut,
+                        half_teleport,
+                        TransformScaleLens {
+                            start: vanished_scale,
+                            end: origin_scale,
+                        },
+                    )
+                    .into(),
+                );
+            }
+        }
+        start = end;
+        previous = waypoint.position();
+    }
+    Sequence::new(tweens)
+}
+
+/// 刚被推走的冰块，等 update_level 重新生成贴图后播放滑动动画
+#[derive(Resource, Default)]
+pub struct PushedBlocks(pub Vec<BlockPush>);
+
+// 冰块的贴图已经在终点，先放回起点，等鸭子撞上以后再滑过去
+fn animate_pushed_blocks(
+    mut commands: Commands,
+    mut pushed_blocks: ResMut<PushedBlocks>,
+    mut query: Query<(Entity, &level::IceBlockSprite, &mut Transform), Added<level::IceBlockSprite>>,
+) {
+    if pushed_blocks.0.is_empty() || query.is_empty() {
+        return;
+    }
+    for push in pushed_blocks.0.drain(..) {
+        let Some((entity, _, mut transform)) = query.iter_mut().find(|(_, block, _)| block.0 == push.to) else {
+            continue;
+        };
+        let start = logic_position_to_translation(push.from);
+        let start = Vec3::new(start.x, start.y, transform.translation.z);
+        let end = transform.translation;
+        transform.translation = start;
+        let tween = Tween::new(
+            EaseFunction::QuadraticOut,
+            Duration::from_millis(DUCK_MOVE_MILI_SECS),
+            TransformPositionLens { start, end },
+        );
+        let delay = Delay::new(Duration::from_millis(DUCK_MOVE_MILI_SECS));
+        commands.entity(entity).insert(Animator::new(delay.then(tween)));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::Direction::{Down, Left, Right, Up};
+
+    fn level(rows: &[&str]) -> level::Level {
+        level::Level(rows.iter().map(|row| row.chars().collect()).collect())
+    }
+
+    fn duck(position: (usize, usize)) -> CommonDuck {
+        CommonDuck {
+            logic_position: position,
+            can_move: true,
+            bread_count: 0,
+            loaves: 0,
+            belly_capacity: 1,
+            heavy: false,
+            peppers: 0,
+            ability: Ability::StopEarly,
+            ability_uses: 0,
+            owner: NEUTRAL,
+        }
+    }
+
+    // 只有一只鸭子，滑一次，返回终点
+    fn slide(level: &mut level::Level, from: (usize, usize), direction: utils::Direction) -> (usize, usize) {
+        slip(&mut duck(from), direction, level, &level::Switches::default(), false).end()
+    }
+
+    fn bread_left(level: &level::Level) -> usize {
+        level
+            .0
+            .iter()
+            .flatten()
+            .filter(|&&ch| level::SymbolType::from_symbol(ch).is_some_and(level::SymbolType::is_bread))
+            .count()
+    }
+
+    // 按解法走一遍关卡文件，每一步是（那只鸭子现在的位置，方向），解法的步数就是标准步数
+    fn solve(content: &str, moves: &[((usize, usize), utils::Direction)]) {
+        let mut level = level::parse_level(content);
+        let meta = level::LevelMeta::parse(content);
+        let switches = level::Switches::new(&level.0, &meta);
+        let mut ducks: Vec<CommonDuck> = level
+            .0
+            .iter()
+            .enumerate()
+            .flat_map(|(row, cells)| cells.iter().enumerate().map(move |(col, &ch)| ((row, col), ch)))
+            .filter(|(_, ch)| level::SymbolType::from_symbol(*ch).is_some_and(level::SymbolType::is_duck))
+            .map(|(position, _)| duck(position))
+            .collect();
+        for &(from, direction) in moves {
+            let duck = ducks
+                .iter_mut()
+                .find(|duck| duck.logic_position == from)
+                .unwrap_or_else(|| panic!("no duck at {:?}", from));
+            assert!(duck.can_move, "duck at {:?} is stuck", from);
+            let end = slip(duck, direction, &mut level, &switches, false).end();
+            duck.logic_position = end;
+        }
+        assert_eq!(bread_left(&level), 0);
+        assert_eq!(meta.par, Some(moves.len() as u32));
+    }
+
+    #[test]
+    fn arrow_turns_a_sliding_duck() {
+        let mut level = level(&["@@@@@", "@D#v@", "@###@", "@@@@@"]);
+        assert_eq!(slide(&mut level, (1, 1), Right), (2, 3));
+        assert_eq!(level.0[1][3], 'v');
+        assert_eq!(level.0[2][3], 'D');
+    }
+
+    #[test]
+    fn arrow_cannot_be_entered_against_it() {
+        let mut level = level(&["@@@@@@", "@D#<#@", "@@@@@@"]);
+        assert_eq!(slide(&mut level, (1, 1), Right), (1, 2));
+        assert_eq!(level.0[1][3], '<');
+    }
+
+    #[test]
+    fn arrow_demo_level() {
+        solve(
+            include_str!("../../assets/levels/level21.txt"),
+            &[((1, 1), Down), ((4, 3), Left), ((4, 2), Up), ((5, 7), Up)],
+        );
+    }
+
+    #[test]
+    fn portal_sends_the_duck_on_from_its_twin() {
+        let mut level = level(&["@@@@@@@@", "@D1@@1#@", "@@@@@@@@"]);
+        let path = slip(&mut duck((1, 1)), Right, &mut level, &level::Switches::default(), false);
+        assert!(path.waypoints.iter().any(|waypoint| matches!(waypoint, Waypoint::Teleport((1, 5)))));
+        assert_eq!(path.end(), (1, 6));
+        assert_eq!(level.0[1][2], '1');
+        assert_eq!(level.0[1][5], '1');
+    }
+
+    #[test]
+    fn portal_with_blocked_exit_or_no_twin_is_a_wall() {
+        let mut blocked = level(&["@@@@@@@@", "@D#1@1@@", "@@@@@@@@"]);
+        assert_eq!(slide(&mut blocked, (1, 1), Right), (1, 2));
+        let mut unpaired = level(&["@@@@@", "@D#2@", "@@@@@"]);
+        assert_eq!(slide(&mut unpaired, (1, 1), Right), (1, 2));
+    }
+
+    #[test]
+    fn portal_demo_level() {
+        solve(
+            include_str!("../../assets/levels/level22.txt"),
+            &[((3, 8), Up), ((4, 1), Right), ((4, 4), Up), ((3, 4), Left)],
+        );
+    }
+
+    #[test]
+    fn pushed_block_slides_until_it_hits_something() {
+        let mut level = level(&["@@@@@@", "@K##B@", "@@@@@@"]);
+        let push = push_block(&mut level, (1, 1), Right).unwrap();
+        assert_eq!((push.from, push.to, push.filled_water), ((1, 1), (1, 3), false));
+        assert_eq!(level.0[1], vec!['@', '#', '#', 'K', 'B', '@']);
+    }
+
+    #[test]
+    fn pushed_block_fills_water() {
+        let mut level = level(&["@@@@@@", "@K#^#@", "@@@@@@"]);
+        let push = push_block(&mut level, (1, 1), Right).unwrap();
+        assert_eq!((push.to, push.filled_water), ((1, 3), true));
+        assert_eq!(level.0[1], vec!['@', '#', '#', '#', '#', '@']);
+    }
+
+    #[test]
+    fn block_that_cannot_move_is_a_wall() {
+        let mut level = level(&["@@@@@", "@DK+@", "@@@@@"]);
+        let path = slip(&mut duck((1, 1)), Right, &mut level, &level::Switches::default(), false);
+        assert!(path.pushed.is_none());
+        assert!(!path.changed_level((1, 1)));
+        assert_eq!(level.0[1], vec!['@', 'D', 'K', '+', '@']);
+    }
+
+    #[test]
+    fn duck_slides_over_water() {
+        let mut level = level(&["@@@@@@", "@D#^#@", "@@@@@@"]);
+        let mut duck = duck((1, 1));
+        assert_eq!(slip(&mut duck, Right, &mut level, &level::Switches::default(), false).end(), (1, 4));
+        assert!(duck.can_move);
+        assert_eq!(level.0[1][3], '^');
+    }
+
+    #[test]
+    fn duck_swims_across_breaking_ice_once() {
+        let swimmer = |position| CommonDuck {
+            ability: Ability::Swim,
+            ability_uses: 1,
+            ..duck(position)
+        };
+        let switches = level::Switches::default();
+        let mut pond = level(&["@@@@@@", "@D#*#@", "@@@@@@"]);
+        let mut duck = swimmer((1, 1));
+        assert_eq!(slip(&mut duck, Right, &mut pond, &switches, false).end(), (1, 4));
+        assert_eq!(pond.0[1], vec!['@', '#', '#', '^', 'D', '@']);
+        assert_eq!(duck.ability_uses, 0);
+        // 用完以后和别的小动物一样掉下去
+        let mut pond = level(&["@@@@@@", "@D#*#@", "@@@@@@"]);
+        duck.logic_position = (1, 1);
+        assert_eq!(slip(&mut duck, Right, &mut pond, &switches, false).end(), (1, 3));
+        assert!(!duck.can_move);
+        // 对岸是墙时停在冰前面，能力还留着
+        let mut pond = level(&["@@@@@", "@D#*@", "@@@@@"]);
+        let mut duck = swimmer((1, 1));
+        assert_eq!(slip(&mut duck, Right, &mut pond, &switches, false).end(), (1, 2));
+        assert_eq!(duck.ability_uses, 1);
+    }
+
+    #[test]
+    fn block_demo_level() {
+        solve(
+            include_str!("../../assets/levels/level23.txt"),
+            &[((1, 5), Down), ((2, 1), Right), ((1, 5), Down), ((4, 5), Left)],
+        );
+    }
+
+    #[test]
+    fn switch_demo_level() {
+        solve(
+            include_str!("../../assets/levels/level24.txt"),
+            &[((4, 1), Up), ((1, 1), Right), ((3, 1), Right), ((3, 4), Right)],
+        );
+    }
+
+    #[test]
+    fn ice_cracks_each_time_a_duck_passes() {
+        let mut level = level(&["@@@@@@", "@D$+#@", "@@@@@@"]);
+        assert_eq!(slide(&mut level, (1, 1), Right), (1, 4));
+        assert_eq!(level.0[1], vec!['@', '#', '+', '*', 'D', '@']);
+        let mut duck = duck((1, 4));
+        slip(&mut duck, Left, &mut level, &level::Switches::default(), false);
+        assert!(!duck.can_move);
+        assert_eq!(level.0[1], vec!['@', '#', '+', 'P', '#', '@']);
+    }
+
+    #[test]
+    fn duck_stopped_on_ice_that_cracked_under_it_can_still_move() {
+        let mut level = level(&["@@@@@", "@D+@@", "@@#@@", "@@@@@"]);
+        let mut duck = duck((1, 1));
+        assert_eq!(slip(&mut duck, Right, &mut level, &level::Switches::default(), false).end(), (1, 2));
+        assert!(duck.can_move);
+        assert_eq!(level.0[1][2], 'T');
+        duck.logic_position = (1, 2);
+        assert_eq!(slip(&mut duck, Down, &mut level, &level::Switches::default(), false).end(), (2, 2));
+        assert_eq!(level.0[1][2], '*');
+    }
+
+    #[test]
+    fn cracked_ice_demo_level() {
+        solve(
+            include_str!("../../assets/levels/level25.txt"),
+            &[((2, 3), Up), ((1, 3), Right), ((4, 1), Up), ((3, 1), Right), ((3, 4), Up), ((2, 4), Right)],
+        );
+    }
+
+    #[test]
+    fn golden_bread_is_worth_more_but_fills_the_same() {
+        let mut level = level(&["@@@@@", "@DG#@", "@@@@@"]);
+        let mut duck = duck((1, 1));
+        slip(&mut duck, Right, &mut level, &level::Switches::default(), false);
+        assert_eq!((duck.bread_count, duck.loaves), (3, 1));
+        assert!(duck.is_full());
+    }
+
+    #[test]
+    fn pepper_lets_the_duck_slide_on_after_eating() {
+        let mut level = level(&["@@@@@@@", "@DRB##@", "@@@@@@@"]);
+        let mut duck = duck((1, 1));
+        assert_eq!(slip(&mut duck, Right, &mut level, &level::Switches::default(), false).end(), (1, 5));
+        assert_eq!((duck.bread_count, duck.peppers), (1, 0));
+        assert_eq!(level.0[1], vec!['@', '#', '#', '#', '#', 'Q', '@']);
+    }
+
+    #[test]
+    fn heavy_duck_leaves_breaking_ice_behind() {
+        let mut level = level(&["@@@@@@", "@DH##@", "@@@@@@"]);
+        let mut duck = duck((1, 1));
+        assert_eq!(slip(&mut duck, Right, &mut level, &level::Switches::default(), false).end(), (1, 2));
+        assert!(duck.heavy);
+        // 吃沉面包的这一下还不会压裂冰面
+        assert_eq!(level.0[1][1], '#');
+        duck.logic_position = (1, 2);
+        slip(&mut duck, Right, &mut level, &level::Switches::default(), false);
+        assert_eq!(level.0[1], vec!['@', '#', '*', '*', 'Q', '@']);
+    }
+
+    #[test]
+    fn bread_demo_level() {
+        solve(
+            include_str!("../../assets/levels/level26.txt"),
+            &[((1, 1), Down), ((4, 1), Right), ((4, 2), Up), ((2, 2), Right), ((4, 6), Left), ((4, 4), Up)],
+        );
+    }
+
+    #[test]
+    fn duck_demo_level() {
+        solve(include_str!("../../assets/levels/level27.txt"), &[((1, 1), Right), ((3, 1), Right)]);
+    }
+}
@@ -0,0 +1,130 @@
+use renet::RenetServer;
+
+use super::{
+    level::{CurrentLevelIndex, CustomLevel},
+    progress::LevelProgress,
+    storage, *,
+};
+use crate::networking::ai::{AiDifficulty, AiOpponent};
+
+// The player's profile: progress, preferred character and settings.
+// Loaded on startup and written back to the config dir whenever it changes.
+
+const PROFILE_FILE: &str = "profile.ron";
+
+pub struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, load_profile).add_systems(
+            Update,
+            save_profile
+                // 服务器和客户端可能在同一台机器上，只让客户端写存档
+                .run_if(not(resource_exists::<RenetServer>))
+                .run_if(resource_exists::<Profile>),
+        );
+    }
+}
+
+#[derive(Resource, Default, Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Shown on the leaderboards, defaults to the OS user name
+    pub name: String,
+    /// Solved levels and best results, also decides which levels are unlocked
+    pub progress: LevelProgress,
+    pub preferred_character: Option<CharacterType>,
+    /// The offline game to resume with "Continue"
+    pub last_session: Option<LastSession>,
+    pub settings: Settings,
+}
+
+impl Profile {
+    pub fn player_name(&self) -> String {
+        if !self.name.is_empty() {
+            return self.name.clone();
+        }
+        std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| "Player".to_string())
+    }
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct LastSession {
+    pub play_mode: PlayMode,
+    pub level: usize,
+    pub player1: CharacterType,
+    pub player2: CharacterType,
+    /// 和 AI 对战时 AI 的难度
+    #[serde(default)]
+    pub opponent: Option<AiDifficulty>,
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            music_volume: 0.03,
+            sfx_volume: 1.0,
+        }
+    }
+}
+
+fn load_profile(mut commands: Commands, mut selected_characters: ResMut<SelectedCharacters>) {
+    let profile: Profile = storage::load(PROFILE_FILE).unwrap_or_default();
+    selected_characters.player1 = profile.preferred_character;
+    commands.insert_resource(profile.progress.clone());
+    commands.insert_resource(profile);
+}
+
+fn save_profile(
+    mut profile: ResMut<Profile>,
+    progress: Res<LevelProgress>,
+    level_index: Res<CurrentLevelIndex>,
+    selected_characters: Res<SelectedCharacters>,
+    play_mode: Res<PlayMode>,
+    custom_level: Res<CustomLevel>,
+    state: Res<State<GameStates>>,
+    ai: Option<Res<AiOpponent>>,
+) {
+    if !(progress.is_changed()
+        || level_index.is_changed()
+        || selected_characters.is_changed()
+        || state.is_changed())
+    {
+        return;
+    }
+
+    let before = ron::to_string(&*profile).ok();
+    profile.progress = progress.clone();
+    if selected_characters.player1.is_some() {
+        profile.preferred_character = selected_characters.player1;
+    }
+    // 录像和编辑器试玩的关卡不能继续，联网的对局也不能
+    if *state.get() == GameStates::Next && custom_level.0.is_none() && play_mode.is_offline() {
+        if let (Some(player1), Some(player2)) =
+            (selected_characters.player1, selected_characters.player2)
+        {
+            profile.last_session = Some(LastSession {
+                play_mode: *play_mode,
+                level: level_index.0,
+                player1,
+                player2,
+                opponent: ai.map(|ai| ai.difficulty),
+            });
+        }
+    }
+
+    if ron::to_string(&*profile).ok() != before {
+        if let Err(e) = storage::save(PROFILE_FILE, &*profile) {
+            warn!("Failed to save profile: {}", e);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{level::{custom_level_loaded, CurrentLevelIndex, LevelCleared}, *};
+
+pub struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MoveCounter>()
+            .init_resource::<LevelProgress>()
+            .add_systems(OnEnter(GameStates::Next), show_solo_progress)
+            .add_systems(
+                Update,
+                (
+                    // 看录像和试玩自己做的关卡不算通关
+                    record_completion
+                        .run_if(not(resource_equals(PlayMode::Replay)))
+                        .run_if(not(custom_level_loaded)),
+                    update_solo_progress,
+                )
+                    .run_if(in_state(GameStates::Next)),
+            )
+            .add_systems(OnExit(GameStates::Next), cleanup_solo_progress);
+    }
+}
+
+// Moves made in the current level, reset whenever the level is (re)loaded
+#[derive(Resource, Default, Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct MoveCounter {
+    pub player1: u32,
+    pub player2: u32,
+}
+
+impl MoveCounter {
+    pub fn add(&mut self, player_id: u8) {
+        match player_id {
+            1 => self.player1 += 1,
+            2 => self.player2 += 1,
+            _ => {}
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.player1 + self.player2
+    }
+}
+
+// 3 stars within par, 2 stars within one and a half par, otherwise 1
+pub fn star_rating(moves: u32, par: u32) -> u32 {
+    if moves <= par {
+        3
+    } else if moves * 2 <= par * 3 {
+        2
+    } else {
+        1
+    }
+}
+
+// Which levels are solved, the fewest moves and the fastest race time
+#[derive(Resource, Default, Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct LevelProgress {
+    pub completed: BTreeSet<usize>,
+    pub best_moves: BTreeMap<usize, u32>,
+    #[serde(default)]
+    pub best_times: BTreeMap<usize, f32>,
+}
+
+impl LevelProgress {
+    pub fn is_completed(&self, level_index: usize) -> bool {
+        self.completed.contains(&level_index)
+    }
+
+    // The first level is always open, every other one once the level before is solved
+    pub fn is_unlocked(&self, level_index: usize) -> bool {
+        level_index <= 1 || self.is_completed(level_index) || self.is_completed(level_index - 1)
+    }
+
+    pub fn record(&mut self, level_index: usize, moves: u32) {
+        self.completed.insert(level_index);
+        let best = self.best_moves.entry(level_index).or_insert(moves);
+        *best = (*best).min(moves);
+    }
+
+    pub fn record_time(&mut self, level_index: usize, seconds: f32) {
+        let best = self.best_times.entry(level_index).or_insert(seconds);
+        *best = best.min(seconds);
+    }
+}
+
+fn record_completion(
+    mut events: EventReader<LevelCleared>,
+    level_index: Res<CurrentLevelIndex>,
+    move_counter: Res<MoveCounter>,
+    mut progress: ResMut<LevelProgress>,
+) {
+    for _ in events.read() {
+        progress.record(level_index.0, move_counter.total());
+    }
+}
+
+#[derive(Component)]
+pub struct SoloProgressText;
+
+fn show_solo_progress(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    play_mode: Res<PlayMode>,
+    query: Query<Entity, With<SoloProgressText>>,
+) {
+    if *play_mode != PlayMode::Solo || !query.is_empty() {
+        return;
+    }
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/NotJamChunky8.ttf"),
+                font_size: 20.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(50.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        SoloProgressText,
+    ));
+}
+
+fn update_solo_progress(
+    level_index: Res<CurrentLevelIndex>,
+    progress: Res<LevelProgress>,
+    mut query: Query<&mut Text, With<SoloProgressText>>,
+) {
+    for mut text in query.iter_mut() {
+        let mut parts = Vec::new();
+        if let Some(best) = progress.best_moves.get(&level_index.0) {
+            parts.push(format!("Best: {}", best));
+        }
+        if progress.is_completed(level_index.0) {
+            parts.push("Solved".to_string());
+        }
+        text.sections[0].value = parts.join("  ");
+    }
+}
+
+fn cleanup_solo_progress(mut commands: Commands, query: Query<Entity, With<SoloProgressText>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+}
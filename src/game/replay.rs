@@ -0,0 +1,590 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::time::Stopwatch;
+use renet::RenetServer;
+
+use super::{
+    cursor::{hand_over, ArrowHint},
+    level::{BreadRecord, CurrentLevelIndex, CustomLevel, Level, LevelStarted, UndoLevelEvent},
+    player::{CommonDuck, MoveApplied, Player1, Player2},
+    progress::MoveCounter,
+    ui::{GameHints, LevelTitle, MutUI, StuffedDucksCount, Won},
+    utils::Direction,
+    storage, *,
+};
+use crate::networking::{ai::AiOpponent, turns::CurrentTurn, RemotePlayerMove};
+
+// Every level that gets played is recorded to the data dir. A recording is
+// played back by feeding the moves to the normal slide rules again, so it
+// looks exactly like the real game.
+
+pub struct Plugin;
+
+impl bevy::app::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplayRecorder>()
+            .add_systems(
+                Update,
+                record_replay
+                    .run_if(in_state(GameStates::Next))
+                    // 服务器和客户端可能在同一台机器上，只让客户端录像
+                    .run_if(not(resource_exists::<RenetServer>))
+                    .run_if(not(resource_equals(PlayMode::Replay))),
+            )
+            .add_systems(OnExit(GameStates::Next), finish_recording)
+            .add_systems(OnEnter(GameStates::Replays), setup_replay_list)
+            .add_systems(
+                Update,
+                (replay_list_input, replay_button_interaction).run_if(in_state(GameStates::Replays)),
+            )
+            .add_systems(OnExit(GameStates::Replays), cleanup_replay_list)
+            .add_systems(
+                OnEnter(GameStates::Next),
+                show_replay_hud.run_if(resource_exists::<ReplayPlayback>),
+            )
+            .add_systems(
+                Update,
+                (replay_controls, advance_replay, update_replay_hud)
+                    .chain()
+                    .run_if(in_state(GameStates::Next))
+                    .run_if(resource_exists::<ReplayPlayback>),
+            )
+            .add_systems(
+                OnExit(GameStates::Next),
+                cleanup_replay.run_if(resource_equals(PlayMode::Replay)),
+            );
+    }
+}
+
+const REPLAY_DIR: &str = "replays";
+// Older recordings are deleted
+const MAX_REPLAYS: usize = 20;
+const LISTED_REPLAYS: usize = 6;
+// Moves are stored in ticks of 1/60 s
+const TICKS_PER_SECOND: f32 = 60.0;
+const SPEEDS: [f32; 5] = [0.5, 1.0, 2.0, 4.0, 8.0];
+// Give up waiting for a move that never got applied
+const MOVE_TIMEOUT_FRAMES: u32 = 30;
+// Frames to wait after an undo so the respawned ducks exist
+const UNDO_SETTLE_FRAMES: u32 = 3;
+
+const NORMAL_BUTTON: Color = MY_ORANGE;
+const HOVERED_BUTTON: Color =
+    Color::srgb(222.0 / 255.0 + 0.1, 112.0 / 255.0 + 0.1, 40.0 / 255.0 + 0.1);
+const PRESSED_BUTTON: Color = Color::srgb(0.75, 0.75, 0.75);
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Replay {
+    pub level_index: usize,
+    /// The board when the level started
+    pub level: Level,
+    pub play_mode: PlayMode,
+    pub player1: Option<CharacterType>,
+    pub player2: Option<CharacterType>,
+    pub steps: Vec<ReplayStep>,
+}
+
+impl Replay {
+    fn move_count(&self) -> usize {
+        self.steps
+            .iter()
+            .filter(|step| matches!(step.action, ReplayAction::Move { .. }))
+            .count()
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ReplayStep {
+    pub tick: u32,
+    pub action: ReplayAction,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub enum ReplayAction {
+    /// `from` tells which duck moved, the player may have switched ducks by clicking
+    Move {
+        player_id: u8,
+        direction: Direction,
+        from: (usize, usize),
+        /// 旧录像里没有这一项
+        #[serde(default)]
+        use_ability: bool,
+    },
+    Undo {
+        level: Level,
+        bread_record: BreadRecord,
+        moves: Option<MoveCounter>,
+    },
+}
+
+#[derive(Resource, Default)]
+struct ReplayRecorder {
+    replay: Option<Replay>,
+    clock: Stopwatch,
+}
+
+impl ReplayRecorder {
+    // Only worth keeping if somebody moved
+    fn save(&mut self) {
+        let Some(replay) = self.replay.take() else {
+            return;
+        };
+        if replay.steps.is_empty() {
+            return;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis());
+        let file_name = format!("{}/{}-level{}.ron", REPLAY_DIR, timestamp, replay.level_index);
+        if let Err(e) = storage::save(&file_name, &replay) {
+            warn!("Failed to save replay: {}", e);
+            return;
+        }
+
+        let names = storage::list(REPLAY_DIR);
+        for name in names.iter().take(names.len().saturating_sub(MAX_REPLAYS)) {
+            if let Err(e) = storage::remove(&format!("{}/{}", REPLAY_DIR, name)) {
+                warn!("Failed to remove old replay: {}", e);
+            }
+        }
+    }
+}
+
+fn record_replay(
+    time: Res<Time>,
+    mut recorder: ResMut<ReplayRecorder>,
+    mut started: EventReader<LevelStarted>,
+    mut moves: EventReader<MoveApplied>,
+    mut undos: EventReader<UndoLevelEvent>,
+    mut won: EventReader<Won>,
+    play_mode: Res<PlayMode>,
+    selected_characters: Res<SelectedCharacters>,
+) {
+    recorder.clock.tick(time.delta());
+    for LevelStarted { level_index, level } in started.read() {
+        // 重新开始也算一局新的录像
+        recorder.save();
+        recorder.replay = Some(Replay {
+            level_index: *level_index,
+            level: level.clone(),
+            play_mode: *play_mode,
+            player1: selected_characters.player1,
+            player2: selected_characters.player2,
+            steps: Vec::new(),
+        });
+        recorder.clock.reset();
+    }
+
+    let tick = (recorder.clock.elapsed_secs() * TICKS_PER_SECOND) as u32;
+    let mut actions: Vec<ReplayAction> = moves
+        .read()
+        .map(|event| ReplayAction::Move {
+            player_id: event.player_id,
+            direction: event.direction,
+            from: event.from,
+            use_ability: event.use_ability,
+        })
+        .collect();
+    actions.extend(undos.read().map(|event| ReplayAction::Undo {
+        level: event.level_data.clone(),
+        bread_record: event.bread_record.clone(),
+        moves: event.moves,
+    }));
+    if let Some(replay) = &mut recorder.replay {
+        replay
+            .steps
+            .extend(actions.into_iter().map(|action| ReplayStep { tick, action }));
+    }
+
+    if won.read().count() > 0 {
+        recorder.save();
+    }
+}
+
+fn finish_recording(mut recorder: ResMut<ReplayRecorder>) {
+    recorder.save();
+}
+
+#[derive(Component)]
+struct ReplayListEntity;
+
+#[derive(Component, Clone)]
+enum ReplayListButton {
+    Play(String),
+    Back,
+}
+
+fn setup_replay_list(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/NotJamChunky8.ttf");
+    // Newest first
+    let replays: Vec<(String, Replay)> = storage::list(REPLAY_DIR)
+        .into_iter()
+        .rev()
+        .filter_map(|name| {
+            let replay = storage::load::<Replay>(&format!("{}/{}", REPLAY_DIR, name))?;
+            Some((name, replay))
+        })
+        .take(LISTED_REPLAYS)
+        .collect();
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(15.0),
+                    padding: UiRect::top(Val::Px(60.0)),
+                    ..default()
+                },
+                ..default()
+            },
+            ReplayListEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "REPLAYS",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 60.0,
+                    color: MY_ORANGE,
+                },
+            ));
+            if replays.is_empty() {
+                parent.spawn(TextBundle::from_section(
+                    "No replays yet",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 24.0,
+                        color: Color::WHITE,
+                    },
+                ));
+            }
+            for (name, replay) in replays {
+                let label = format!(
+                    "Level{}  {:?}  {} moves",
+                    replay.level_index,
+                    replay.play_mode,
+                    replay.move_count()
+                );
+                spawn_replay_button(parent, &font, &label, ReplayListButton::Play(name), 640.0);
+            }
+            spawn_replay_button(parent, &font, "Back", ReplayListButton::Back, 160.0);
+        });
+}
+
+fn spawn_replay_button(
+    parent: &mut ChildBuilder,
+    font: &Handle<Font>,
+    label: &str,
+    button: ReplayListButton,
+    width: f32,
+) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(width),
+                    height: Val::Px(55.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: NORMAL_BUTTON.into(),
+                ..default()
+            },
+            button,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                label,
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 24.0,
+                    color: Color::WHITE,
+                },
+            ));
+        });
+}
+
+fn replay_list_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<GameStates>>,
+) {
+    if keyboard.just_pressed(KeyCode::Escape) {
+        next_state.set(GameStates::GameMenu);
+    }
+}
+
+fn replay_button_interaction(
+    mut interaction_query: Query<
+        (&Interaction, &mut BackgroundColor, &ReplayListButton),
+        Changed<Interaction>,
+    >,
+    mut commands: Commands,
+    mut play_mode: ResMut<PlayMode>,
+    mut selected_characters: ResMut<SelectedCharacters>,
+    mut level_index: ResMut<CurrentLevelIndex>,
+    mut custom_level: ResMut<CustomLevel>,
+    mut next_state: ResMut<NextState<GameStates>>,
+) {
+    for (interaction, mut color, button) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *color = PRESSED_BUTTON.into();
+                match button {
+                    ReplayListButton::Play(name) => {
+                        let Some(replay) = storage::load::<Replay>(&format!("{}/{}", REPLAY_DIR, name)) else {
+                            warn!("Failed to load replay {}", name);
+                            continue;
+                        };
+                        // 录像里的棋盘和角色，不经过服务器
+                        *play_mode = PlayMode::Replay;
+                        commands.remove_resource::<AiOpponent>();
+                        commands.insert_resource(CurrentTurn::default());
+                        selected_characters.player1 = replay.player1;
+                        selected_characters.player2 = replay.player2;
+                        level_index.0 = replay.level_index;
+                        custom_level.0 = Some(replay.level.clone());
+                        commands.insert_resource(ReplayPlayback::new(replay));
+                        next_state.set(GameStates::Next);
+                    }
+                    ReplayListButton::Back => next_state.set(GameStates::GameMenu),
+                }
+            }
+            Interaction::Hovered => {
+                *color = HOVERED_BUTTON.into();
+            }
+            Interaction::None => {
+                *color = NORMAL_BUTTON.into();
+            }
+        }
+    }
+}
+
+fn cleanup_replay_list(mut commands: Commands, query: Query<Entity, With<ReplayListEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    replay: Replay,
+    next_step: usize,
+    /// In ticks since the level started
+    clock: f32,
+    speed_index: usize,
+    paused: bool,
+    step_once: bool,
+    awaiting_move: u32,
+    hold_frames: u32,
+}
+
+impl ReplayPlayback {
+    fn new(replay: Replay) -> Self {
+        ReplayPlayback {
+            replay,
+            next_step: 0,
+            clock: 0.0,
+            speed_index: 1,
+            paused: false,
+            step_once: false,
+            awaiting_move: 0,
+            hold_frames: 0,
+        }
+    }
+
+    fn rewind(&mut self) {
+        self.next_step = 0;
+        self.clock = 0.0;
+        self.awaiting_move = 0;
+        self.hold_frames = UNDO_SETTLE_FRAMES;
+    }
+
+    fn speed(&self) -> f32 {
+        SPEEDS[self.speed_index]
+    }
+}
+
+fn replay_controls(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut playback: ResMut<ReplayPlayback>,
+    mut next_state: ResMut<NextState<GameStates>>,
+) {
+    if keyboard.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+    }
+    // 暂停并走一步
+    if keyboard.just_pressed(KeyCode::ArrowRight) {
+        playback.paused = true;
+        playback.step_once = true;
+    }
+    if keyboard.just_pressed(KeyCode::ArrowUp) {
+        playback.speed_index = (playback.speed_index + 1).min(SPEEDS.len() - 1);
+    }
+    if keyboard.just_pressed(KeyCode::ArrowDown) {
+        playback.speed_index = playback.speed_index.saturating_sub(1);
+    }
+    if keyboard.just_pressed(KeyCode::Escape) {
+        commands.remove_resource::<ReplayPlayback>();
+        next_state.set(GameStates::Replays);
+    }
+}
+
+fn advance_replay(
+    mut commands: Commands,
+    time: Res<Time>,
+    image_assets: Res<ImageAssets>,
+    mut playback: ResMut<ReplayPlayback>,
+    mut started: EventReader<LevelStarted>,
+    mut applied: EventReader<MoveApplied>,
+    duck_query: Query<(Entity, &CommonDuck, Has<Player1>, Has<Player2>)>,
+    mut move_writer: EventWriter<RemotePlayerMove>,
+    mut undo_writer: EventWriter<UndoLevelEvent>,
+) {
+    // 关卡重新生成时从头播放
+    if started.read().count() > 0 {
+        playback.rewind();
+    }
+    if applied.read().count() > 0 {
+        playback.awaiting_move = 0;
+    }
+    if playback.hold_frames > 0 {
+        playback.hold_frames -= 1;
+        return;
+    }
+    // 上一步还没滑完之前不能把标记交给别的鸭子
+    if playback.awaiting_move > 0 {
+        playback.awaiting_move -= 1;
+        return;
+    }
+    if !playback.paused {
+        playback.clock += time.delta_seconds() * TICKS_PER_SECOND * playback.speed();
+    }
+
+    let Some(step) = playback.replay.steps.get(playback.next_step).cloned() else {
+        return;
+    };
+    if !playback.step_once && step.tick as f32 > playback.clock {
+        return;
+    }
+
+    match step.action {
+        ReplayAction::Move {
+            player_id,
+            direction,
+            from,
+            use_ability,
+        } => {
+            let target = duck_query
+                .iter()
+                .find(|(_, duck, _, _)| duck.logic_position == from);
+            match target {
+                Some((_, _, has_player1, has_player2)) => {
+                    let has_marker = if player_id == 1 { has_player1 } else { has_player2 };
+                    if !has_marker {
+                        // 下一帧标记到位后再移动
+                        hand_over(&mut commands, &image_assets, &duck_query, from, player_id);
+                        return;
+                    }
+                    move_writer.send(RemotePlayerMove {
+                        player_id,
+                        direction,
+                        use_ability,
+                    });
+                    playback.awaiting_move = MOVE_TIMEOUT_FRAMES;
+                }
+                None => warn!("Replay out of sync, no duck at {:?}", from),
+            }
+        }
+        ReplayAction::Undo { level, bread_record, moves } => {
+            undo_writer.send(UndoLevelEvent {
+                level_data: level,
+                bread_record,
+                moves,
+            });
+            playback.hold_frames = UNDO_SETTLE_FRAMES;
+        }
+    }
+    playback.clock = playback.clock.max(step.tick as f32);
+    playback.next_step += 1;
+    playback.step_once = false;
+}
+
+#[derive(Component)]
+struct ReplayHud;
+
+fn show_replay_hud(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    query: Query<Entity, With<ReplayHud>>,
+) {
+    if !query.is_empty() {
+        return;
+    }
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/NotJamChunky8.ttf"),
+                font_size: 20.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(50.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        ReplayHud,
+    ));
+}
+
+fn update_replay_hud(playback: Res<ReplayPlayback>, mut query: Query<&mut Text, With<ReplayHud>>) {
+    let total = playback.replay.steps.len();
+    let status = if playback.next_step >= total {
+        "  End"
+    } else if playback.paused {
+        "  Paused"
+    } else {
+        ""
+    };
+    for mut text in query.iter_mut() {
+        text.sections[0].value = format!(
+            "REPLAY x{}  {}/{}{}\nSpace pause  Right step  Up/Down speed  Esc exit",
+            playback.speed(),
+            playback.next_step,
+            total,
+            status
+        );
+    }
+}
+
+fn cleanup_replay(
+    mut commands: Commands,
+    mut play_mode: ResMut<PlayMode>,
+    mut custom_level: ResMut<CustomLevel>,
+    query: Query<
+        Entity,
+        Or<(
+            With<ReplayHud>,
+            With<level::Object>,
+            With<ArrowHint>,
+            With<GameHints>,
+            With<LevelTitle>,
+            With<StuffedDucksCount>,
+            With<MutUI>,
+        )>,
+    >,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    *play_mode = PlayMode::default();
+    custom_level.0 = None;
+}
@@ -1,5 +1,5 @@
 use bevy::{prelude::*, input::ButtonInput};
-use super::{CharacterType, GameStates, ImageAssets, SelectedCharacters, MY_ORANGE};
+use super::{character::Characters, CharacterType, GameStates, SelectedCharacters, MY_ORANGE};
 
 pub struct Plugin;
 
@@ -33,7 +33,7 @@ const CHARACTER_IMAGE_SIZE: Val = Val::Px(200.0);
 fn setup_selection_ui(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    image_assets: Res<ImageAssets>,
+    characters: Res<Characters>,
 ) {
     commands.spawn((
         NodeBundle {
@@ -89,10 +89,9 @@ fn setup_selection_ui(
                 },
                 ..default()
             }).with_children(|parent| {
-                spawn_character_image(parent, &image_assets, CharacterType::Duck);
-                spawn_character_image(parent, &image_assets, CharacterType::Cat);
-                spawn_character_image(parent, &image_assets, CharacterType::Bunny);
-                spawn_character_image(parent, &image_assets, CharacterType::Chick);
+                for char_type in characters.all() {
+                    spawn_character_image(parent, &characters, char_type);
+                }
             });
 
             parent.spawn(NodeBundle {
@@ -105,10 +104,26 @@ fn setup_selection_ui(
                 },
                 ..default()
             }).with_children(|parent| {
-                spawn_character_button(parent, &asset_server, CharacterType::Duck);
-                spawn_character_button(parent, &asset_server, CharacterType::Cat);
-                spawn_character_button(parent, &asset_server, CharacterType::Bunny);
-                spawn_character_button(parent, &asset_server, CharacterType::Chick);
+                for char_type in characters.all() {
+                    spawn_character_button(parent, &asset_server, &characters, char_type);
+                }
+            });
+
+            // 每个角色的饭量和能力
+            parent.spawn(NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::Row,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::FlexStart,
+                    column_gap: BUTTON_SPACING,
+                    margin: UiRect::top(Val::Px(10.0)),
+                    ..default()
+                },
+                ..default()
+            }).with_children(|parent| {
+                for char_type in characters.all() {
+                    spawn_character_info(parent, &asset_server, &characters, char_type);
+                }
             });
 
 
@@ -133,13 +148,8 @@ fn setup_selection_ui(
     });
 }
 
-fn spawn_character_image(parent: &mut ChildBuilder, image_assets: &Res<ImageAssets>, char_type: CharacterType) {
-    let texture = match char_type {
-        CharacterType::Duck => image_assets.duck.clone(),
-        CharacterType::Cat => image_assets.cat.clone(),
-        CharacterType::Bunny => image_assets.bunny.clone(),
-        CharacterType::Chick => image_assets.chick.clone(),
-    };
+fn spawn_character_image(parent: &mut ChildBuilder, characters: &Characters, char_type: CharacterType) {
+    let texture = characters.sprite(char_type, false);
 
     parent.spawn(ImageBundle {
         style: Style {
@@ -152,7 +162,12 @@ fn spawn_character_image(parent: &mut ChildBuilder, image_assets: &Res<ImageAsse
     });
 }
 
-fn spawn_character_button(parent: &mut ChildBuilder, asset_server: &Res<AssetServer>, char_type: CharacterType) {
+fn spawn_character_button(
+    parent: &mut ChildBuilder,
+    asset_server: &Res<AssetServer>,
+    characters: &Characters,
+    char_type: CharacterType,
+) {
     parent.spawn((
         ButtonBundle {
             style: Style {
@@ -168,12 +183,7 @@ fn spawn_character_button(parent: &mut ChildBuilder, asset_server: &Res<AssetSer
         CharacterButton(char_type),
     )).with_children(|parent| {
         parent.spawn(TextBundle::from_section(
-            match char_type {
-                CharacterType::Duck => "Duck",
-                CharacterType::Cat => "Cat",
-                CharacterType::Chick => "Chick",
-                CharacterType::Bunny => "Bunny",
-            },
+            characters.stats(char_type).name.clone(),
             TextStyle {
                 font: asset_server.load("fonts/NotJamChunky8.ttf"),
                 font_size: 24.0,
@@ -183,6 +193,35 @@ fn spawn_character_button(parent: &mut ChildBuilder, asset_server: &Res<AssetSer
     });
 }
 
+fn spawn_character_info(
+    parent: &mut ChildBuilder,
+    asset_server: &Res<AssetServer>,
+    characters: &Characters,
+    char_type: CharacterType,
+) {
+    let stats = characters.stats(char_type);
+    let uses = match stats.ability_uses {
+        Some(uses) => format!(" ({}/level)", uses),
+        None => String::new(),
+    };
+    let text = format!("Belly: {}\n{}{}", stats.belly_capacity, stats.description, uses);
+    parent.spawn(TextBundle {
+        style: Style {
+            width: BUTTON_SIZE,
+            ..default()
+        },
+        text: Text::from_section(
+            text,
+            TextStyle {
+                font: asset_server.load("fonts/NotJamChunky8.ttf"),
+                font_size: 12.0,
+                color: Color::WHITE,
+            },
+        ),
+        ..default()
+    });
+}
+
 use bevy::input::mouse::MouseButtonInput;
 
 
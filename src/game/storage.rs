@@ -0,0 +1,47 @@
+use std::{fs, path::PathBuf};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+// Small helpers for reading and writing RON files in the platform config dir
+// e.g. ~/.config/battle_on_ice on linux, %APPDATA%\battle_on_ice on windows
+
+const APP_DIR: &str = "battle_on_ice";
+
+pub fn data_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_DIR))
+}
+
+pub fn load<T: DeserializeOwned>(file_name: &str) -> Option<T> {
+    let path = data_dir()?.join(file_name);
+    let content = fs::read_to_string(path).ok()?;
+    ron::from_str(&content).ok()
+}
+
+pub fn save<T: Serialize>(file_name: &str, value: &T) -> anyhow::Result<()> {
+    let dir = data_dir().ok_or_else(|| anyhow::anyhow!("No config dir on this platform"))?;
+    let path = dir.join(file_name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let content = ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())?;
+    fs::write(path, content)?;
+    Ok(())
+}
+
+// File names in a sub directory of the data dir, sorted by name
+pub fn list(sub_dir: &str) -> Vec<String> {
+    let Some(entries) = data_dir().and_then(|dir| fs::read_dir(dir.join(sub_dir)).ok()) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    names
+}
+
+pub fn remove(file_name: &str) -> anyhow::Result<()> {
+    let dir = data_dir().ok_or_else(|| anyhow::anyhow!("No config dir on this platform"))?;
+    fs::remove_file(dir.join(file_name))?;
+    Ok(())
+}
@@ -3,8 +3,10 @@ use bevy::window::PrimaryWindow;
 use self::level::Levels;
 
 use super::{
-    cursor::click_detection, level::{BreadCount, CurrentLevelIndex, TotalBreadCount}, *
+    cursor::click_detection, level::{BreadCount, CurrentLevelIndex, TotalBreadCount},
+    progress::{star_rating, MoveCounter}, *
 };
+use crate::networking::{cheats_allowed, Permissions};
 pub struct Plugin;
 
 impl bevy::app::Plugin for Plugin {
@@ -13,6 +15,8 @@ impl bevy::app::Plugin for Plugin {
         
         .add_systems(OnEnter(GameStates::Next), show_level_title)
         .add_systems(OnEnter(GameStates::Next), show_stuffed_ducks_count)
+        .add_systems(OnEnter(GameStates::Next), show_move_count)
+        .add_systems(OnExit(GameStates::Next), cleanup_move_count)
         .add_event::<Won>()
         .add_systems(
             Update,
@@ -28,7 +32,8 @@ impl bevy::app::Plugin for Plugin {
                 //next_level_button_interaction.after(click_detection),
             ),
         )
-        .add_systems(Update, update_stuffed_ducks_count.run_if(in_state(GameStates::Next)));
+        .add_systems(Update, update_stuffed_ducks_count.run_if(in_state(GameStates::Next)))
+        .add_systems(Update, update_move_count.run_if(in_state(GameStates::Next)));
     }
 }
 
@@ -126,6 +131,64 @@ fn update_stuffed_ducks_count(
     }
 }
 
+// 每个玩家的步数和本关的标准步数，显示在面包计数下面
+#[derive(Component)]
+pub struct MoveCount;
+
+fn show_move_count(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    query: Query<Entity, With<MoveCount>>,
+) {
+    if !query.is_empty() {
+        return;
+    }
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/NotJamChunky8.ttf"),
+                font_size: 16.0,
+                ..default()
+            },
+        )
+        .with_text_justify(JustifyText::Center)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(48.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            ..default()
+        }),
+        MoveCount,
+    ));
+}
+
+fn update_move_count(
+    move_counter: Res<MoveCounter>,
+    level_index: Res<CurrentLevelIndex>,
+    levels: Res<Levels>,
+    play_mode: Res<PlayMode>,
+    mut query: Query<&mut Text, With<MoveCount>>,
+) {
+    let mut value = match *play_mode {
+        PlayMode::Solo => format!("Moves {}", move_counter.total()),
+        _ => format!("P1 {}  P2 {}", move_counter.player1, move_counter.player2),
+    };
+    if let Some(par) = levels.meta(level_index.0).par {
+        value.push_str(&format!("  Par {}", par));
+    }
+    for mut text in query.iter_mut() {
+        text.sections[0].value = value.clone();
+    }
+}
+
+fn cleanup_move_count(mut commands: Commands, query: Query<Entity, With<MoveCount>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
 #[derive(Component)]
 pub struct LevelTitle;
 
@@ -159,10 +222,22 @@ fn show_level_title(
 // WASD to move
 // R to reset
 // Z to undo
+// Y to redo
+// L to select a level
+// [ ] to skip levels, only when cheats are allowed
 #[derive(Component)]
 pub struct GameHints;
 
-fn show_hints(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn show_hints(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    play_mode: Res<PlayMode>,
+    permissions: Option<Res<Permissions>>,
+    hints_query: Query<(), With<GameHints>>,
+) {
+    if !hints_query.is_empty() {
+        return;
+    }
     let text_style_important = TextStyle {
         font: asset_server.load("fonts/NotJamChunky8.ttf"),
         font_size: 20.0,
@@ -173,18 +248,25 @@ fn show_hints(mut commands: Commands, asset_server: Res<AssetServer>) {
         font_size: 20.0,
         ..default()
     };
-    commands.spawn((TextBundle::from_sections([
+    let mut sections = vec![
         TextSection::new("Click ", text_style_important.clone()),
         TextSection::new("to choose your sprite\n", text_style_normal.clone()),
         TextSection::new("WASD or Arrow ", text_style_important.clone()),
         TextSection::new("to move\n", text_style_normal.clone()),
         TextSection::new("R ", text_style_important.clone()),
         TextSection::new("to reset\n", text_style_normal.clone()),
-        //TextSection::new("Z ", text_style_important.clone()),
-        //TextSection::new("to undo\n", text_style_normal.clone()),
-        TextSection::new("[ ] ", text_style_important.clone()),
-        TextSection::new("to skip levels\n\n", text_style_normal.clone()),
-    ])
+        TextSection::new("Z ", text_style_important.clone()),
+        TextSection::new("to undo\n", text_style_normal.clone()),
+        TextSection::new("Y ", text_style_important.clone()),
+        TextSection::new("to redo\n", text_style_normal.clone()),
+        TextSection::new("L ", text_style_important.clone()),
+        TextSection::new("to select level\n", text_style_normal.clone()),
+    ];
+    if cheats_allowed(play_mode, permissions) {
+        sections.push(TextSection::new("[ ] ", text_style_important.clone()));
+        sections.push(TextSection::new("to skip levels\n", text_style_normal.clone()));
+    }
+    commands.spawn((TextBundle::from_sections(sections)
     .with_text_justify(JustifyText::Right)
     .with_style(Style {
         position_type: PositionType::Absolute,
@@ -235,6 +317,8 @@ fn won(
     window_query: Query<&Window, With<PrimaryWindow>>,
     level_index: Res<CurrentLevelIndex>,
     levels: Res<Levels>,
+    move_counter: Res<MoveCounter>,
+    image_assets: Res<ImageAssets>,
 ) {
     for _ in events.read() {
         let window = window_query.get_single().unwrap();
@@ -257,6 +341,60 @@ fn won(
             MutUI,
         ));
 
+        // Star rating against the level's par, earned stars are drawn as bread
+        if let Some(par) = levels.meta(level_index.0).par {
+            let moves = move_counter.total();
+            let stars = star_rating(moves, par);
+            commands
+                .spawn((
+                    NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            top: Val::Px(90.0),
+                            width: Val::Percent(100.0),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            column_gap: Val::Px(8.0),
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    MutUI,
+                ))
+                .with_children(|parent| {
+                    for star in 1..=3 {
+                        let color = if star <= stars {
+                            Color::WHITE
+                        } else {
+                            Color::srgba(0.3, 0.3, 0.3, 0.6)
+                        };
+                        parent.spawn((
+                            ImageBundle {
+                                style: Style {
+                                    width: Val::Px(48.0),
+                                    height: Val::Px(48.0),
+                                    ..default()
+                                },
+                                image: UiImage::new(image_assets.bread.clone()).with_color(color),
+                                ..default()
+                            },
+                            MutUI,
+                        ));
+                    }
+                    parent.spawn((
+                        TextBundle::from_section(
+                            format!("{} moves / par {}", moves, par),
+                            TextStyle {
+                                font: asset_server.load("fonts/NotJamChunky8.ttf"),
+                                font_size: 16.0,
+                                ..default()
+                            },
+                        ),
+                        MutUI,
+                    ));
+                });
+        }
+
         let mut button_text = "Next Level";
         if level_index.0 == levels.levels.len() {
             button_text = "You WIN!";
@@ -310,8 +448,7 @@ fn won(
     }
 }
 
-use renet::RenetClient;
-use crate::networking::ClientMessage;
+use crate::networking::{ClientMessage, ServerLink};
 fn next_level_button_interaction(
     mut interaction_query: Query<
         (&Interaction, &mut BackgroundColor, &mut BorderColor),
@@ -319,7 +456,7 @@ fn next_level_button_interaction(
     >,
     mut level_index: ResMut<CurrentLevelIndex>,
     levels: Res<level::Levels>,
-    mut client: Option<ResMut<RenetClient>>,
+    mut link: ServerLink,
 
 ) {
     // Handle invalid level index
@@ -334,12 +471,9 @@ fn next_level_button_interaction(
             Interaction::Pressed => {
                 *color = PRESSED_BUTTON.into();
                 border_color.0 = Color::WHITE;
-                // If we're the client, send next level request
-                if let Some(client) = &mut client {
-                    let message = bincode::serialize(&ClientMessage::NextLevelRequest).unwrap();
-                    client.send_message(0, message);
-                    info!("next level request!");
-                } 
+                // Send next level request to the server
+                link.send(ClientMessage::NextLevelRequest);
+                info!("next level request!");
 
             }
 
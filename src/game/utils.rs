@@ -10,6 +10,18 @@ pub enum Direction {
     None,
 }
 
+impl Direction {
+    pub fn opposite(self) -> Self {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+            Direction::None => Direction::None,
+        }
+    }
+}
+
 // impl Direction {
 //     pub fn to_vector(self) -> Vec2 {
 //         match self {
@@ -31,6 +43,16 @@ pub fn logic_position_to_translation(logic_position: (usize, usize)) -> Vec3 {
     )
 }
 
+// The grid cell under a point in the world, if it is right or below the first cell
+pub fn translation_to_logic_position(translation: Vec2) -> Option<(usize, usize)> {
+    let column = ((translation.x - 720.0 / 4.0) / SPRITE_SIZE).round();
+    let row = ((720.0 * (3.0 / 4.0) - translation.y) / SPRITE_SIZE).round();
+    if column < 0.0 || row < 0.0 {
+        return None;
+    }
+    Some((row as usize, column as usize))
+}
+
 // Define a generic Stack struct
 pub struct Stack<T> {
     items: Vec<T>,
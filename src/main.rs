@@ -7,6 +7,8 @@ use bevy_tweening::TweeningPlugin;
 mod game;
 mod networking;
 use networking::ServerAddress;
+use networking::ai::{AiDifficulty, AiOpponent};
+use networking::MatchRules;
 fn main() {
     let args: Vec<String> = std::env::args().collect();
     let is_server = args.iter().any(|arg| arg == "--server");
@@ -20,12 +22,33 @@ fn main() {
         None
     };
 
+    // 没有 --connect 时在菜单里选择服务器
     let server_ip = args.iter()
     .position(|arg| arg == "--connect")
     .and_then(|i| args.get(i + 1))
-    .cloned()
-    .unwrap_or("127.0.0.1".to_string());
+    .cloned();
 
+    // --ai easy|hard：由服务器上的 AI 控制 Player2
+    let ai_difficulty = args.iter()
+    .position(|arg| arg == "--ai")
+    .map(|i| args.get(i + 1).and_then(|arg| AiDifficulty::from_arg(arg)).unwrap_or(AiDifficulty::Easy));
+
+    // --turn-based 轮流移动，--turn-clock SECS 每回合限时
+    let turn_clock = args.iter()
+    .position(|arg| arg == "--turn-clock")
+    .and_then(|i| args.get(i + 1))
+    .and_then(|secs| secs.parse::<f32>().ok());
+    // --race SECS 竞速模式，每关限时
+    let race_secs = args.iter()
+    .position(|arg| arg == "--race")
+    .and_then(|i| args.get(i + 1))
+    .and_then(|secs| secs.parse::<f32>().ok());
+    let match_rules = MatchRules {
+        turn_based: turn_clock.is_some() || args.iter().any(|arg| arg == "--turn-based"),
+        turn_clock,
+        race_secs,
+        allow_cheats: args.iter().any(|arg| arg == "--allow-cheats"),
+    };
 
     let window_title = if is_server {
         "Bevy Jam 4 🦀 -server".into()
@@ -58,13 +81,26 @@ fn main() {
         .add_plugins(TweeningPlugin)
         .add_plugins(WindowResizePlugin)
         .add_plugins(game::Plugin)
+        .add_plugins(networking::local::LocalServerPlugin)
+        .add_plugins(networking::ai::AiPlugin)
+        .add_plugins(networking::turns::TurnPlugin)
+        .add_plugins(networking::race::RacePlugin)
+        .add_plugins(networking::undo::UndoPlugin)
+        .insert_resource(match_rules)
         .init_state::<game::GameStates>();
 
+    if let Some(difficulty) = ai_difficulty {
+        app.insert_resource(AiOpponent::new(difficulty));
+        info!("AI opponent enabled: {:?}", difficulty);
+    }
+
     if is_server {
         app.add_plugins(networking::server::ServerPlugin);
         info!("Running as SERVER.");
     } else {
-        app.insert_resource(ServerAddress(server_ip));
+        if let Some(server_ip) = server_ip {
+            app.insert_resource(ServerAddress(server_ip));
+        }
         app.add_plugins(networking::client::ClientPlugin);
         if let Some(id) = client_identity {
             info!("Running as CLIENT with identity hint: {}", id);
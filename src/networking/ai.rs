@@ -0,0 +1,402 @@
+// src/networking/ai.rs
+// AI 对手：作为服务器内部的虚拟客户端控制 Player2，
+// 走和真人一样的 SelectDuck 和 PlayerMovementInput 流程
+use bevy::prelude::*;
+use std::collections::{HashSet, VecDeque};
+
+use super::*;
+use super::turns::TurnState;
+use crate::game::level::{Switches, SymbolType};
+use crate::game::player::{slip, CommonDuck, Duck, Player2};
+use crate::game::utils::Direction;
+
+pub struct AiPlugin;
+
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<VirtualClientMessage>().add_systems(
+            Update,
+            (
+                join_character_selection.run_if(in_state(GameStates::CharacterSelection)),
+                take_turn.run_if(in_state(GameStates::Next)),
+                answer_undo_request.run_if(in_state(GameStates::Next)),
+                answer_level_proposal.run_if(in_state(GameStates::LevelSelect)),
+            )
+                .run_if(resource_exists::<AiOpponent>),
+        );
+    }
+}
+
+/// AI 占用的座位
+pub const AI_PLAYER_ID: u8 = 2;
+const HUMAN: u8 = 1;
+const BOT: u8 = AI_PLAYER_ID;
+const DIRECTIONS: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+// 估值时搜索面包的最大步数
+const REACH_DEPTH: u32 = 6;
+// 困难模式的搜索层数（双方各走两步）
+const MINIMAX_DEPTH: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AiDifficulty {
+    /// 贪心：走最短的路去吃面包
+    Easy,
+    /// 对双方所有的鸭子做 minimax 搜索
+    Hard,
+}
+
+impl AiDifficulty {
+    pub fn from_arg(arg: &str) -> Option<Self> {
+        match arg {
+            "easy" => Some(AiDifficulty::Easy),
+            "hard" => Some(AiDifficulty::Hard),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct AiOpponent {
+    pub difficulty: AiDifficulty,
+    think_timer: Timer,
+}
+
+impl AiOpponent {
+    pub fn new(difficulty: AiDifficulty) -> Self {
+        let think_secs = match difficulty {
+            AiDifficulty::Easy => 1.2,
+            AiDifficulty::Hard => 0.8,
+        };
+        AiOpponent {
+            difficulty,
+            think_timer: Timer::from_seconds(think_secs, TimerMode::Repeating),
+        }
+    }
+}
+
+/// 有 AI 时真人不能再操作 AI 的鸭子
+pub fn is_reserved_for_ai(message: &ClientMessage, ai: Option<&AiOpponent>) -> bool {
+    ai.is_some()
+        && matches!(
+            message,
+            ClientMessage::PlayerMovementInput { player_id: AI_PLAYER_ID, .. }
+                | ClientMessage::SelectDuck { player_id: AI_PLAYER_ID, .. }
+        )
+}
+
+// AI 总是准备好的，没人替它选角色时用小鸡
+fn join_character_selection(
+    selection_state: Res<SelectionState>,
+    mut virtual_messages: EventWriter<VirtualClientMessage>,
+) {
+    if selection_state.player2_ready {
+        return;
+    }
+    if selection_state.player2_choice.is_none() {
+        virtual_messages.send(VirtualClientMessage {
+            player_id: AI_PLAYER_ID,
+            message: ClientMessage::CharacterSelected {
+                player_id: AI_PLAYER_ID,
+                character: CharacterType::from_id("Chick").unwrap_or_default(),
+            },
+        });
+    }
+    virtual_messages.send(VirtualClientMessage {
+        player_id: AI_PLAYER_ID,
+        message: ClientMessage::ReadyForGameStart,
+    });
+}
+
+fn take_turn(
+    time: Res<Time>,
+    mut ai: ResMut<AiOpponent>,
+    level: Res<Level>,
+    switches: Res<Switches>,
+    rules: Res<MatchRules>,
+    play_mode: Res<PlayMode>,
+    turn: Res<TurnState>,
+    duck_query: Query<(&CommonDuck, Has<Player2>)>,
+    mut virtual_messages: EventWriter<VirtualClientMessage>,
+) {
+    if !ai.think_timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    // 回合制时等轮到自己
+    if rules.is_turn_based(*play_mode) && turn.current != AI_PLAYER_ID {
+        return;
+    }
+    let state = SimState {
+        level: level.clone(),
+        switches: switches.clone(),
+        ducks: duck_query.iter().map(|(duck, _)| duck.clone()).collect(),
+    };
+    if state.bread_left() == 0 {
+        return;
+    }
+
+    let choice = match ai.difficulty {
+        AiDifficulty::Easy => search_bread(&state, BOT, u32::MAX).map(|(first_move, _)| first_move),
+        AiDifficulty::Hard => best_minimax_move(&state),
+    };
+    let Some((index, direction)) = choice else {
+        return;
+    };
+    // 要走的不是当前的鸭子就先换过去，下次思考时再走
+    let position = state.ducks[index].logic_position;
+    let active = duck_query
+        .iter()
+        .any(|(duck, is_player2)| is_player2 && duck.logic_position == position);
+    if !active {
+        virtual_messages.send(VirtualClientMessage {
+            player_id: AI_PLAYER_ID,
+            message: ClientMessage::SelectDuck {
+                player_id: AI_PLAYER_ID,
+                position,
+            },
+        });
+    } else {
+        virtual_messages.send(VirtualClientMessage {
+            player_id: AI_PLAYER_ID,
+            message: ClientMessage::PlayerMovementInput {
+                player_id: AI_PLAYER_ID,
+                direction,
+                use_ability: false,
+            },
+        });
+    }
+}
+
+// AI 总是同意对手撤销或重做
+fn answer_undo_request(
+    mut inbound: EventReader<InboundServerMessage>,
+    mut virtual_messages: EventWriter<VirtualClientMessage>,
+) {
+    for InboundServerMessage(message) in inbound.read() {
+        if let ServerMessage::UndoVote { request: Some(request) } = message {
+            if request.player_id != AI_PLAYER_ID {
+                virtual_messages.send(VirtualClientMessage {
+                    player_id: AI_PLAYER_ID,
+                    message: ClientMessage::UndoResponse { accept: true },
+                });
+            }
+        }
+    }
+}
+
+// AI 玩哪一关都可以
+fn answer_level_proposal(
+    mut inbound: EventReader<InboundServerMessage>,
+    mut virtual_messages: EventWriter<VirtualClientMessage>,
+) {
+    for InboundServerMessage(message) in inbound.read() {
+        if let ServerMessage::LevelProposal { proposal: Some(proposal) } = message {
+            if proposal.player_id != AI_PLAYER_ID {
+                virtual_messages.send(VirtualClientMessage {
+                    player_id: AI_PLAYER_ID,
+                    message: ClientMessage::LevelResponse { accept: true },
+                });
+            }
+        }
+    }
+}
+
+// 一步棋：第几只鸭子往哪个方向走
+type Move = (usize, Direction);
+
+// 用 slip 模拟的棋盘，包括场上所有的鸭子
+#[derive(Clone)]
+struct SimState {
+    level: Level,
+    switches: Switches,
+    ducks: Vec<CommonDuck>,
+}
+
+impl SimState {
+    // 棋盘没有任何变化的移动算作无效
+    fn apply(&self, (index, direction): Move) -> Option<SimState> {
+        if !self.ducks[index].can_move() {
+            return None;
+        }
+        let mut next = self.clone();
+        let path = slip(&mut next.ducks[index], direction, &mut next.level, &self.switches, false);
+        if !path.changed_level(self.ducks[index].get_logic_position()) {
+            return None;
+        }
+        let end_position = path.end();
+        next.ducks[index].set_logic_position(end_position);
+        Some(next)
+    }
+
+    // 玩家可以走的所有棋，每只自己的鸭子四个方向
+    fn moves(&self, player: u8) -> Vec<Move> {
+        self.ducks
+            .iter()
+            .enumerate()
+            .filter(|(_, duck)| duck.owner == player)
+            .flat_map(|(index, _)| DIRECTIONS.map(|direction| (index, direction)))
+            .collect()
+    }
+
+    fn children(&self, player: u8) -> impl Iterator<Item = (Move, SimState)> + '_ {
+        self.moves(player)
+            .into_iter()
+            .filter_map(|next_move| self.apply(next_move).map(|next| (next_move, next)))
+    }
+
+    // 玩家所有鸭子吃的面包
+    fn bread(&self, player: u8) -> u32 {
+        self.ducks
+            .iter()
+            .filter(|duck| duck.owner == player)
+            .map(|duck| duck.get_bread_count())
+            .sum()
+    }
+
+    // 去重用：玩家每只鸭子的位置和面包数
+    fn key(&self, player: u8) -> Vec<((usize, usize), u32)> {
+        self.ducks
+            .iter()
+            .filter(|duck| duck.owner == player)
+            .map(|duck| (duck.get_logic_position(), duck.get_bread_count()))
+            .collect()
+    }
+
+    fn bread_left(&self) -> usize {
+        self.level
+            .0
+            .iter()
+            .flatten()
+            .filter(|&&symbol| SymbolType::from_symbol(symbol).is_some_and(SymbolType::is_bread))
+            .count()
+    }
+}
+
+// 广度优先：只移动一方的鸭子，返回吃到下一块面包的第一步和总步数
+fn search_bread(state: &SimState, player: u8, max_depth: u32) -> Option<(Move, u32)> {
+    let start_bread = state.bread(player);
+    let mut visited = HashSet::new();
+    visited.insert(state.key(player));
+    let mut queue = VecDeque::new();
+    queue.push_back((state.clone(), None, 0));
+
+    while let Some((current, first_move, depth)) = queue.pop_front() {
+        if depth >= max_depth {
+            continue;
+        }
+        for (next_move, next) in current.children(player) {
+            let first_move = first_move.unwrap_or(next_move);
+            if next.bread(player) > start_bread {
+                return Some((first_move, depth + 1));
+            }
+            if visited.insert(next.key(player)) {
+                queue.push_back((next, Some(first_move), depth + 1));
+            }
+        }
+    }
+    None
+}
+
+// 面包差为主，离面包的距离为辅
+fn evaluate(state: &SimState) -> i32 {
+    let bread = |player: u8| state.bread(player) as i32;
+    let reach = |player: u8| {
+        search_bread(state, player, REACH_DEPTH).map_or(REACH_DEPTH as i32 + 1, |(_, steps)| steps as i32)
+    };
+    10 * (bread(BOT) - bread(HUMAN)) - reach(BOT) + reach(HUMAN)
+}
+
+fn minimax(state: &SimState, depth: u32, bot_to_move: bool, mut alpha: i32, mut beta: i32) -> i32 {
+    if depth == 0 || state.bread_left() == 0 {
+        return evaluate(state);
+    }
+    let player = if bot_to_move { BOT } else { HUMAN };
+    let children: Vec<SimState> = state.children(player).map(|(_, next)| next).collect();
+    // 动不了就跳过这一步
+    if children.is_empty() {
+        return minimax(state, depth - 1, !bot_to_move, alpha, beta);
+    }
+
+    if bot_to_move {
+        let mut best = i32::MIN;
+        for child in &children {
+            best = best.max(minimax(child, depth - 1, false, alpha, beta));
+            alpha = alpha.max(best);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    } else {
+        let mut best = i32::MAX;
+        for child in &children {
+            best = best.min(minimax(child, depth - 1, true, alpha, beta));
+            beta = beta.min(best);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+}
+
+fn best_minimax_move(state: &SimState) -> Option<Move> {
+    let mut best: Option<(Move, i32)> = None;
+    for (next_move, next) in state.children(BOT) {
+        let score = minimax(&next, MINIMAX_DEPTH - 1, false, i32::MIN, i32::MAX);
+        if best.is_none_or(|(_, best_score)| score > best_score) {
+            best = Some((next_move, score));
+        }
+    }
+    best.map(|(next_move, _)| next_move)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::character::Ability;
+
+    fn duck(position: (usize, usize), owner: u8) -> CommonDuck {
+        CommonDuck {
+            logic_position: position,
+            can_move: true,
+            bread_count: 0,
+            loaves: 0,
+            belly_capacity: 1,
+            heavy: false,
+            peppers: 0,
+            ability: Ability::StopEarly,
+            ability_uses: 0,
+            owner,
+        }
+    }
+
+    fn state(rows: &[&str], ducks: Vec<CommonDuck>) -> SimState {
+        SimState {
+            level: Level(rows.iter().map(|row| row.chars().collect()).collect()),
+            switches: Switches::default(),
+            ducks,
+        }
+    }
+
+    #[test]
+    fn bot_uses_whichever_of_its_ducks_reaches_bread() {
+        // 第一只 AI 的鸭子够不到面包，第二只可以
+        let state = state(
+            &["@D@@@@", "@@@@@@", "@D##B@", "@@@@@@"],
+            vec![duck((0, 1), BOT), duck((2, 1), BOT)],
+        );
+        assert_eq!(search_bread(&state, BOT, u32::MAX), Some(((1, Direction::Right), 1)));
+        assert_eq!(best_minimax_move(&state), Some((1, Direction::Right)));
+    }
+
+    #[test]
+    fn bread_is_counted_over_all_owned_ducks() {
+        let mut ducks = vec![duck((1, 1), HUMAN), duck((1, 3), BOT), duck((1, 5), BOT)];
+        ducks[1].bread_count = 2;
+        ducks[2].bread_count = 1;
+        let state = state(&["@@@@@@@", "@D@D@D@", "@@@@@@@"], ducks);
+        assert_eq!((state.bread(HUMAN), state.bread(BOT)), (0, 3));
+        // 不能替对手的鸭子走棋
+        assert!(state.moves(BOT).iter().all(|(index, _)| *index != 0));
+    }
+}
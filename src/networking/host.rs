@@ -0,0 +1,580 @@
+// src/networking/host.rs
+// 服务器端的消息处理逻辑，网络服务器和本地离线模式共用
+use bevy::{ecs::system::SystemParam, prelude::*};
+use renet::RenetServer;
+
+use super::*;
+use super::race::RaceState;
+use super::server::Seats;
+use super::turns::TurnState;
+use crate::game::leaderboard::ServerLeaderboard;
+use crate::game::level::{Levels, UndoStacks};
+use crate::game::player::{can_select, CommonDuck};
+use crate::game::progress::LevelProgress;
+
+/// 处理一条客户端消息后需要发出的服务器消息
+pub enum Outgoing {
+    /// 通过可靠通道发给所有客户端
+    Broadcast(ServerMessage),
+    /// 通过不可靠通道发给所有客户端
+    BroadcastUnreliable(ServerMessage),
+    /// 只回复发送者
+    Reply(ServerMessage),
+}
+
+impl Outgoing {
+    pub fn into_message(self) -> ServerMessage {
+        match self {
+            Outgoing::Broadcast(message)
+            | Outgoing::BroadcastUnreliable(message)
+            | Outgoing::Reply(message) => message,
+        }
+    }
+}
+
+/// 对战中等待对手同意的撤销或重做请求
+#[derive(Resource, Default)]
+pub struct PendingUndo(pub Option<UndoRequest>);
+
+/// 对战中等待对手同意的关卡
+#[derive(Resource, Default)]
+pub struct PendingLevel(pub Option<LevelProposal>);
+
+/// 这个服务器上通关过的关卡，联网选关时用来判断是否解锁
+#[derive(Resource, Default)]
+pub struct MatchProgress(pub LevelProgress);
+
+/// 服务器端权威状态
+#[derive(SystemParam)]
+pub struct HostContext<'w, 's> {
+    pub selection_state: ResMut<'w, SelectionState>,
+    pub game_state: ResMut<'w, FullGameState>,
+    pub next_state: ResMut<'w, NextState<GameStates>>,
+    pub level: ResMut<'w, Level>,
+    pub undo_stacks: UndoStacks<'w>,
+    pub play_mode: Res<'w, PlayMode>,
+    pub rules: Res<'w, MatchRules>,
+    pub turn: ResMut<'w, TurnState>,
+    pub race: ResMut<'w, RaceState>,
+    pub pending_undo: ResMut<'w, PendingUndo>,
+    pub pending_level: ResMut<'w, PendingLevel>,
+    pub leaderboard: Res<'w, ServerLeaderboard>,
+    pub levels: Res<'w, Levels>,
+    pub match_progress: Res<'w, MatchProgress>,
+    pub ducks: Query<'w, 's, &'static CommonDuck>,
+    /// 离线时没有
+    pub seats: Option<Res<'w, Seats>>,
+}
+
+impl HostContext<'_, '_> {
+    fn is_turn_based(&self) -> bool {
+        self.rules.is_turn_based(*self.play_mode)
+    }
+
+    fn is_racing(&self) -> bool {
+        self.rules.is_racing(*self.play_mode)
+    }
+
+    // 离线时玩家自己就是主机；联网时要服务器用 --allow-cheats 启动，而且只有开服务器的玩家能用
+    fn allows_cheats(&self, player_id: u8) -> bool {
+        self.play_mode.is_offline()
+            || (self.rules.allow_cheats
+                && self.seats.as_ref().is_some_and(|seats| seats.host_seat() == Some(player_id)))
+    }
+
+    // 新的一关（或重开）：回合从 Player1 开始，竞速重新倒计时
+    fn start_round(&mut self, outgoing: &mut Vec<Outgoing>) {
+        self.close_undo_vote(outgoing);
+        if self.is_turn_based() {
+            outgoing.push(Outgoing::Broadcast(self.turn.reset(&self.rules)));
+        }
+        if self.is_racing() {
+            outgoing.push(Outgoing::Broadcast(self.race.start()));
+        }
+    }
+
+    // 选关界面选好以后开始这一关
+    fn start_level(&mut self, index: CurrentLevelIndex, outgoing: &mut Vec<Outgoing>) {
+        self.close_level_proposal(outgoing);
+        self.game_state.current_level = index;
+        outgoing.push(Outgoing::Broadcast(ServerMessage::NextLevelNotification { level_index: index }));
+        info!("Server: starting level {}", index.0);
+        self.start_round(outgoing);
+    }
+
+    fn close_level_proposal(&mut self, outgoing: &mut Vec<Outgoing>) {
+        if self.pending_level.0.take().is_some() {
+            outgoing.push(Outgoing::Broadcast(ServerMessage::LevelProposal { proposal: None }));
+        }
+    }
+
+    fn close_undo_vote(&mut self, outgoing: &mut Vec<Outgoing>) {
+        if self.pending_undo.0.take().is_some() {
+            outgoing.push(Outgoing::Broadcast(ServerMessage::UndoVote { request: None }));
+        }
+    }
+
+    fn can_undo(&self, redo: bool) -> bool {
+        if redo {
+            !self.undo_stacks.redo.0.is_empty()
+        } else {
+            // 至少有两帧状态才能撤销（当前和之前）
+            self.undo_stacks.levels.0.size() >= 2 && self.undo_stacks.bread_records.0.size() >= 2
+        }
+    }
+
+    // 单人模式随时可以撤销，对战时要对手同意
+    fn request_undo(&mut self, player_id: u8, redo: bool, outgoing: &mut Vec<Outgoing>) {
+        // 竞速时不能撤销
+        if self.is_racing() {
+            info!("Undo rejected: racing");
+            return;
+        }
+        if !self.can_undo(redo) {
+            info!("Undo rejected: nothing to {}", if redo { "redo" } else { "undo" });
+            return;
+        }
+        if *self.play_mode == PlayMode::Solo {
+            self.apply_undo(redo, outgoing);
+        } else if self.pending_undo.0.is_none() {
+            let request = UndoRequest { player_id, redo };
+            self.pending_undo.0 = Some(request);
+            outgoing.push(Outgoing::Broadcast(ServerMessage::UndoVote { request: Some(request) }));
+        }
+    }
+
+    fn apply_undo(&mut self, redo: bool, outgoing: &mut Vec<Outgoing>) {
+        if redo {
+            self.redo(outgoing);
+        } else {
+            self.undo(outgoing);
+        }
+        // 撤销的那一步属于上一个玩家，回合还给他
+        if self.is_turn_based() {
+            outgoing.push(Outgoing::Broadcast(self.turn.pass(&self.rules)));
+        }
+    }
+
+    // 回到上一步的棋盘、每只鸭子的面包数和步数
+    fn undo(&mut self, outgoing: &mut Vec<Outgoing>) {
+        let (Some(undone_level), Some(undone_record)) =
+            (self.undo_stacks.levels.0.pop(), self.undo_stacks.bread_records.0.pop())
+        else {
+            return;
+        };
+        let undone_moves = self.undo_stacks.move_counts.0.pop().unwrap_or_default();
+        self.undo_stacks.redo.0.push((undone_level, undone_record, undone_moves));
+        let level = Level(self.undo_stacks.levels.0.peek().unwrap().clone());
+        let bread_record = self.undo_stacks.bread_records.0.peek().unwrap().clone();
+        let moves = self.undo_stacks.move_counts.0.peek().copied().unwrap_or_default();
+        self.level.0 = level.0.clone();
+
+        outgoing.push(Outgoing::Broadcast(ServerMessage::DoUndoLevel {
+            level,
+            bread_record,
+            moves,
+        }));
+        info!("Server broadcasted undo level");
+    }
+
+    fn redo(&mut self, outgoing: &mut Vec<Outgoing>) {
+        let Some((level, bread_record, moves)) = self.undo_stacks.redo.0.pop() else {
+            return;
+        };
+        self.undo_stacks.levels.0.push(level.clone());
+        self.undo_stacks.bread_records.0.push(bread_record.clone());
+        self.undo_stacks.move_counts.0.push(moves);
+        self.level.0 = level.clone();
+
+        outgoing.push(Outgoing::Broadcast(ServerMessage::DoRedoLevel {
+            level: Level(level),
+            bread_record,
+            moves,
+        }));
+        info!("Server broadcasted redo level");
+    }
+}
+
+/// 运行条件：这个进程负责执行服务器逻辑（联网的服务器或离线模式）
+pub fn is_host(play_mode: Res<PlayMode>, server: Option<Res<RenetServer>>) -> bool {
+    server.is_some() || play_mode.is_offline()
+}
+
+/// `player_id` 是发送者占用的座位（1 或 2）
+pub fn process_client_message(
+    player_id: u8,
+    client_message: ClientMessage,
+    host: &mut HostContext,
+) -> Vec<Outgoing> {
+    let mut outgoing = Vec::new();
+    match client_message {
+        ClientMessage::StateChangeRequest(new_state) => {
+            host.game_state.current_state = new_state;
+            host.next_state.set(new_state);
+
+            // 广播新状态给所有客户端
+            outgoing.push(Outgoing::Broadcast(ServerMessage::StateChangeNotification(new_state)));
+
+            info!("State changed to: {:?}", new_state);
+        }
+        ClientMessage::PlayerPositionUpdate(position) => {
+            // 更新玩家位置并广播
+            if player_id == 1 {
+                host.game_state.player1_position = position;
+            } else {
+                host.game_state.player2_position = position;
+            }
+
+            outgoing.push(Outgoing::BroadcastUnreliable(ServerMessage::PlayerPositionUpdate {
+                player_id,
+                position,
+            }));
+        }
+
+        ClientMessage::RequestFullState => {
+            // 发送完整状态给请求的客户端
+            outgoing.push(Outgoing::Reply(ServerMessage::FullStateSync(host.game_state.clone())));
+        }
+        ClientMessage::CharacterSelected { player_id, character } => {
+            match player_id {
+                1 => {
+                    host.selection_state.player1_choice = Some(character);
+                    info!("Player 1 selected {:?}", character);
+                }
+                2 => {
+                    host.selection_state.player2_choice = Some(character);
+                    info!("Player 2 selected {:?}", character);
+                }
+                _ => {}
+            }
+
+            // 广播选择更新给所有客户端
+            outgoing.push(Outgoing::Broadcast(ServerMessage::CharacterSelectionUpdate {
+                player1_choice: host.selection_state.player1_choice,
+                player2_choice: host.selection_state.player2_choice,
+            }));
+        }
+
+        ClientMessage::ReadyForGameStart => {
+            match player_id {
+                1 => {
+                    host.selection_state.player1_ready = true;
+                    info!("Player 1 is ready");
+                }
+                2 => {
+                    host.selection_state.player2_ready = true;
+                    info!("Player 2 is ready");
+                }
+                _ => {}
+            }
+
+            // 检查是否都准备好了
+            if host.selection_state.player1_ready && host.selection_state.player2_ready {
+                info!(
+                    "Both players ready. p1_choice: {:?}, p2_choice: {:?}",
+                    host.selection_state.player1_choice, host.selection_state.player2_choice
+                );
+                if let (Some(p1_char), Some(p2_char)) = (
+                    host.selection_state.player1_choice,
+                    host.selection_state.player2_choice,
+                ) {
+                    // 通知所有客户端开始游戏
+                    info!("start!");
+                    outgoing.push(Outgoing::Broadcast(ServerMessage::StartGameWithCharacters {
+                        player1_character: p1_char,
+                        player2_character: p2_char,
+                    }));
+
+                    // 更新服务器状态
+                    host.game_state.player1_character = p1_char;
+                    host.game_state.player2_character = p2_char;
+                    // 先选关，选好了才开始
+                    host.next_state.set(GameStates::LevelSelect);
+                }
+            }
+        }
+
+        //movement
+        ClientMessage::PlayerMovementInput { player_id: claimed, direction, use_ability } => {
+            // 只能移动自己座位上的鸭子
+            if claimed != player_id {
+                info!("Rejected move from player {}: claimed player {}", player_id, claimed);
+                return outgoing;
+            }
+            if host.is_turn_based() && player_id != host.turn.current {
+                info!("Rejected move from player {}: not their turn", player_id);
+                return outgoing;
+            }
+            if host.is_racing() && !host.race.accepts_moves() {
+                info!("Rejected move from player {}: race is not running", player_id);
+                return outgoing;
+            }
+            // 投票期间棋盘不能变
+            if host.pending_undo.0.is_some() {
+                info!("Rejected move from player {}: undo vote pending", player_id);
+                return outgoing;
+            }
+            outgoing.push(Outgoing::Broadcast(ServerMessage::PlayerMovementUpdate {
+                player_id,
+                direction,
+                use_ability,
+            }));
+            if host.is_turn_based() {
+                outgoing.push(Outgoing::Broadcast(host.turn.pass(&host.rules)));
+            }
+        }
+
+        ClientMessage::NextLevelRequest => {
+            let current = host.game_state.current_level.0;
+            // 没通关就去下一关等于跳关
+            if !host.match_progress.0.is_completed(current) && !host.allows_cheats(player_id) {
+                info!("Rejected next level from player {}: level {} is not solved", player_id, current);
+                return outgoing;
+            }
+            host.game_state.current_level.0 = (current + 1).min(host.levels.levels.len());
+
+            let new_level = host.game_state.current_level;
+
+            info!("Server: advancing to level {}", new_level.0);
+
+            outgoing.push(Outgoing::Broadcast(ServerMessage::NextLevelNotification {
+                level_index: new_level,
+            }));
+            host.start_round(&mut outgoing);
+        }
+
+        ClientMessage::RestartLevel => {
+            outgoing.push(Outgoing::Broadcast(ServerMessage::DoRestartLevel));
+            host.start_round(&mut outgoing);
+        }
+
+        ClientMessage::UndoLevel => {
+            info!("Undo request from player {}", player_id);
+            host.request_undo(player_id, false, &mut outgoing);
+        }
+
+        ClientMessage::RedoLevel => {
+            info!("Redo request from player {}", player_id);
+            host.request_undo(player_id, true, &mut outgoing);
+        }
+
+        ClientMessage::UndoResponse { accept } => {
+            let Some(request) = host.pending_undo.0 else {
+                return outgoing;
+            };
+            // 发起人不能替对手同意，只能取消
+            if player_id == request.player_id && accept {
+                return outgoing;
+            }
+            info!("Player {} answered undo request: {}", player_id, accept);
+            host.close_undo_vote(&mut outgoing);
+            if accept {
+                host.apply_undo(request.redo, &mut outgoing);
+            }
+        }
+
+        ClientMessage::ChangeLevelCheat(index) => {
+            if !host.allows_cheats(player_id) {
+                info!("Rejected level cheat from player {}: cheats are disabled", player_id);
+                return outgoing;
+            }
+            // 记住当前关卡，否则下一关会从旧的关卡继续
+            host.game_state.current_level = index;
+            outgoing.push(Outgoing::Broadcast(ServerMessage::DoChangeLevel(index)));
+            info!("change to{}", index.0);
+            host.start_round(&mut outgoing);
+        }
+
+        ClientMessage::SelectLevel(index) => {
+            if index.0 == 0 || index.0 > host.levels.levels.len() {
+                info!("Rejected level select from player {}: no level {}", player_id, index.0);
+                return outgoing;
+            }
+            // 离线时进度在本机存档里，选关界面已经检查过；联网时按这个服务器上的通关记录，
+            // 和跳关一样能作弊的玩家可以选任意关卡
+            if !host.allows_cheats(player_id) && !host.match_progress.0.is_unlocked(index.0) {
+                info!("Rejected level select from player {}: level {} is locked", player_id, index.0);
+                return outgoing;
+            }
+            // 单人模式直接开始，对战时要对手同意
+            if *host.play_mode == PlayMode::Solo {
+                host.start_level(index, &mut outgoing);
+            } else if host.pending_level.0.is_none() {
+                let proposal = LevelProposal { player_id, level_index: index.0 };
+                host.pending_level.0 = Some(proposal);
+                outgoing.push(Outgoing::Broadcast(ServerMessage::LevelProposal {
+                    proposal: Some(proposal),
+                }));
+            }
+        }
+
+        ClientMessage::LevelResponse { accept } => {
+            let Some(proposal) = host.pending_level.0 else {
+                return outgoing;
+            };
+            // 发起人不能替对手同意，只能取消
+            if player_id == proposal.player_id && accept {
+                return outgoing;
+            }
+            info!("Player {} answered level proposal: {}", player_id, accept);
+            if accept {
+                host.start_level(CurrentLevelIndex(proposal.level_index), &mut outgoing);
+            } else {
+                host.close_level_proposal(&mut outgoing);
+            }
+        }
+
+        // 换鸭子不改变棋盘，什么时候都可以换，但只能换成自己的鸭子（单人模式都是自己的）
+        ClientMessage::SelectDuck { player_id: claimed, position } => {
+            if claimed != player_id {
+                info!("Rejected duck selection from player {}: claimed player {}", player_id, claimed);
+                return outgoing;
+            }
+            let owned = host
+                .ducks
+                .iter()
+                .any(|duck| duck.logic_position == position && can_select(duck, player_id, *host.play_mode));
+            if !owned {
+                info!("Rejected duck selection from player {}: no duck of theirs at {:?}", player_id, position);
+                return outgoing;
+            }
+            outgoing.push(Outgoing::Broadcast(ServerMessage::DuckSelected { player_id, position }));
+        }
+
+        ClientMessage::RequestLeaderboard(level_index) => {
+            outgoing.push(Outgoing::Reply(ServerMessage::LeaderboardUpdate {
+                level_index,
+                scores: host.leaderboard.0.top(level_index).to_vec(),
+            }));
+        }
+    }
+    outgoing
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::state::app::StatesPlugin;
+    use crate::game::level::{
+        initial_record, BreadSumRecordStack, LevelMeta, LevelStack, MoveCountStack, RedoStack,
+    };
+    use crate::game::player::NEUTRAL;
+    use crate::game::SelectedCharacters;
+
+    /// 只有服务器逻辑用到的资源，不加载图片和窗口
+    pub(crate) fn host_app(play_mode: PlayMode) -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, StatesPlugin))
+            .init_state::<GameStates>()
+            .insert_resource(play_mode)
+            .init_resource::<SelectionState>()
+            .init_resource::<FullGameState>()
+            .init_resource::<Level>()
+            .init_resource::<LevelStack>()
+            .init_resource::<BreadSumRecordStack>()
+            .init_resource::<MoveCountStack>()
+            .init_resource::<RedoStack>()
+            .init_resource::<MatchRules>()
+            .init_resource::<TurnState>()
+            .init_resource::<RaceState>()
+            .init_resource::<PendingUndo>()
+            .init_resource::<PendingLevel>()
+            .init_resource::<ServerLeaderboard>()
+            .init_resource::<Levels>()
+            .init_resource::<MatchProgress>()
+            .add_event::<VirtualClientMessage>();
+        app
+    }
+
+    /// 处理一条消息，返回要发出的服务器消息
+    pub(crate) fn process(app: &mut App, player_id: u8, message: ClientMessage) -> Vec<ServerMessage> {
+        app.world_mut().run_system_once_with(
+            (player_id, message),
+            |In((player_id, message)): In<(u8, ClientMessage)>, mut host: HostContext| {
+                process_client_message(player_id, message, &mut host)
+                    .into_iter()
+                    .map(Outgoing::into_message)
+                    .collect()
+            },
+        )
+    }
+
+    #[test]
+    fn next_level_needs_the_current_level_solved() {
+        let mut app = host_app(PlayMode::Online);
+        assert!(process(&mut app, 1, ClientMessage::NextLevelRequest).is_empty());
+        assert_eq!(app.world().resource::<FullGameState>().current_level.0, 1);
+
+        app.world_mut().resource_mut::<MatchProgress>().0.record(1, 10);
+        process(&mut app, 1, ClientMessage::NextLevelRequest);
+        assert_eq!(app.world().resource::<FullGameState>().current_level.0, 2);
+    }
+
+    #[test]
+    fn undo_to_the_start_keeps_the_owners_from_the_level_file() {
+        let mut app = host_app(PlayMode::Local);
+        let content = ";duck 0 1 2\r\n;duck 0 3 1\r\n@D#D#B@";
+        let start = crate::game::level::parse_level(content);
+        let owners = LevelMeta::parse(content).owners;
+        let characters = crate::game::character::tests::characters();
+        let record = initial_record(&start.0, &owners, PlayMode::Local, &SelectedCharacters::default(), &characters);
+        // 开始一关时和走了一步以后的两帧
+        let mut moved = start.clone();
+        moved.0[0][1] = '#';
+        moved.0[0][2] = 'D';
+        let mut moved_record = record.clone();
+        moved_record[0].0 = (0, 2);
+        let mut stacks = app.world_mut().resource_mut::<LevelStack>();
+        stacks.0.push(start.0.clone());
+        stacks.0.push(moved.0);
+        let mut records = app.world_mut().resource_mut::<BreadSumRecordStack>();
+        records.0.push(record.clone());
+        records.0.push(moved_record);
+
+        process(&mut app, 1, ClientMessage::UndoLevel);
+        let replies = process(&mut app, 2, ClientMessage::UndoResponse { accept: true });
+        let restored = replies.iter().find_map(|message| match message {
+            ServerMessage::DoUndoLevel { bread_record, .. } => Some(bread_record.clone()),
+            _ => None,
+        });
+        let owners = restored.unwrap().iter().map(|(_, duck)| duck.owner).collect::<Vec<_>>();
+        assert_eq!(owners, [2, 1]);
+    }
+
+    #[test]
+    fn cannot_select_the_other_players_duck() {
+        let mut app = host_app(PlayMode::Online);
+        let duck = |position, owner| CommonDuck {
+            logic_position: position,
+            can_move: true,
+            bread_count: 0,
+            loaves: 0,
+            belly_capacity: 1,
+            heavy: false,
+            peppers: 0,
+            ability: crate::game::character::Ability::Swim,
+            ability_uses: 0,
+            owner,
+        };
+        app.world_mut().spawn_batch([duck((1, 1), 2), duck((1, 3), 1), duck((1, 5), NEUTRAL)]);
+        let select = |position| ClientMessage::SelectDuck { player_id: 1, position };
+        assert!(process(&mut app, 1, select((1, 1))).is_empty());
+        assert!(process(&mut app, 1, select((1, 5))).is_empty());
+        assert!(matches!(
+            process(&mut app, 1, select((1, 3)))[..],
+            [ServerMessage::DuckSelected { player_id: 1, position: (1, 3) }]
+        ));
+        // 座位 2 也不能冒充座位 1
+        assert!(process(&mut app, 2, select((1, 3))).is_empty());
+    }
+
+    #[test]
+    fn next_level_stops_at_the_last_level() {
+        let mut app = host_app(PlayMode::Solo);
+        let last = app.world().resource::<Levels>().levels.len();
+        app.world_mut().resource_mut::<FullGameState>().current_level = CurrentLevelIndex(last);
+        process(&mut app, 1, ClientMessage::NextLevelRequest);
+        assert_eq!(app.world().resource::<FullGameState>().current_level.0, last);
+    }
+}
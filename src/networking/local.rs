@@ -0,0 +1,72 @@
+// src/networking/local.rs
+// 离线模式：在本进程里运行服务器逻辑，不需要 --server
+use bevy::prelude::*;
+use renet::RenetServer;
+
+use super::ai::{is_reserved_for_ai, AiOpponent};
+use super::host::{process_client_message, HostContext, MatchProgress, PendingLevel, PendingUndo};
+use super::*;
+
+pub struct LocalServerPlugin;
+
+impl Plugin for LocalServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<LocalClientMessage>()
+            .add_event::<InboundServerMessage>()
+            .add_event::<ServerBroadcast>()
+            .init_resource::<FullGameState>()
+            .init_resource::<SelectionState>()
+            .init_resource::<PendingUndo>()
+            .init_resource::<PendingLevel>()
+            .init_resource::<MatchProgress>()
+            .add_systems(Update, run_local_server.run_if(is_local_host));
+    }
+}
+
+// 联网时由 server.rs 处理消息，两边都处理的话 AI 的每一步会被执行两遍
+fn is_local_host(play_mode: Res<PlayMode>, server: Option<Res<RenetServer>>) -> bool {
+    play_mode.is_offline() && server.is_none()
+}
+
+fn run_local_server(
+    mut messages: EventReader<LocalClientMessage>,
+    mut virtual_messages: EventReader<VirtualClientMessage>,
+    mut broadcasts: EventReader<ServerBroadcast>,
+    mut inbound: EventWriter<InboundServerMessage>,
+    ai: Option<Res<AiOpponent>>,
+    mut host: HostContext,
+) {
+    for LocalClientMessage(client_message) in messages.read() {
+        if is_reserved_for_ai(client_message, ai.as_deref()) {
+            continue;
+        }
+        // 本地玩家同时占用两个座位，和 AI 对战时只占一个
+        let player_ids: &[u8] = match client_message {
+            ClientMessage::ReadyForGameStart if ai.is_none() => &[1, 2],
+            // 撤销请求和选关总是来自座位 1，同一个键盘上的回应算作对手的
+            ClientMessage::UndoResponse { .. } | ClientMessage::LevelResponse { .. } if ai.is_none() => &[2],
+            // 两个人共用键盘时，按键决定是哪个座位在移动或换鸭子
+            ClientMessage::PlayerMovementInput { player_id, .. } | ClientMessage::SelectDuck { player_id, .. }
+                if ai.is_none() =>
+            {
+                std::slice::from_ref(player_id)
+            }
+            _ => &[1],
+        };
+        for &player_id in player_ids {
+            for outgoing in process_client_message(player_id, client_message.clone(), &mut host) {
+                inbound.send(InboundServerMessage(outgoing.into_message()));
+            }
+        }
+    }
+
+    for VirtualClientMessage { player_id, message } in virtual_messages.read() {
+        for outgoing in process_client_message(*player_id, message.clone(), &mut host) {
+            inbound.send(InboundServerMessage(outgoing.into_message()));
+        }
+    }
+
+    for ServerBroadcast(message) in broadcasts.read() {
+        inbound.send(InboundServerMessage(message.clone()));
+    }
+}
@@ -1,12 +1,16 @@
 // src/networking/mod.rs
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
+use renet::RenetClient;
 use serde::{Deserialize, Serialize};
 use crate::game::level::CurrentLevelIndex;
-use crate::game::GameStates;
+use crate::game::{GameStates, PlayMode};
 // 协议ID，用于客户端和服务器之间匹配
 pub const PROTOCOL_ID: u64 = 7;
+// 默认端口
+pub const DEFAULT_PORT: u16 = 5000;
 use crate::game::utils::Direction;
-use crate::game::level::Level;
+use crate::game::level::{BreadRecord, Level};
+use crate::game::progress::MoveCounter;
 
 use bevy::prelude::Resource;
 //server address
@@ -45,6 +49,7 @@ impl Default for ServerChannels {
 }
 
 use crate::game::CharacterType;
+use crate::game::leaderboard::Score;
 /// 客户端发送给服务器的消息
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ClientMessage {
@@ -64,12 +69,28 @@ pub enum ClientMessage {
     PlayerMovementInput {
     player_id: u8,
     direction: Direction,
+    /// 按住 Shift 时使用角色的主动能力
+    use_ability: bool,
     }, 
     NextLevelRequest,   
 
     RestartLevel,
+    /// 单人模式直接撤销，对战时发起撤销请求
     UndoLevel,
+    /// 重做刚刚撤销的一步，规则和撤销一样
+    RedoLevel,
+    /// 回应对手的撤销/重做请求，发起人回应 false 表示取消
+    UndoResponse { accept: bool },
+    /// [ ] 跳关，只有离线或服务器允许作弊时才生效
     ChangeLevelCheat(CurrentLevelIndex),
+    /// 从选关界面选择一个已解锁的关卡，对战时要对手同意
+    SelectLevel(CurrentLevelIndex),
+    /// 回应对手选的关卡，发起人回应 false 表示取消
+    LevelResponse { accept: bool },
+    /// 请求某一关的排行榜
+    RequestLeaderboard(usize),
+    /// 换成操作自己在这个位置上的另一只鸭子
+    SelectDuck { player_id: u8, position: (usize, usize) },
 
 }
 
@@ -94,19 +115,82 @@ pub enum ServerMessage {
     
     PlayerMovementUpdate {
         player_id: u8,
-        direction: Direction
+        direction: Direction,
+        use_ability: bool,
     },
+    /// 玩家换了要操作的鸭子
+    DuckSelected { player_id: u8, position: (usize, usize) },
     
     NextLevelNotification {
         level_index: CurrentLevelIndex,
     },
 
     DoRestartLevel,
-    DoUndoLevel(Level),
+    DoUndoLevel {
+        level: Level,
+        bread_record: BreadRecord,
+        moves: MoveCounter,
+    },
+    DoRedoLevel {
+        level: Level,
+        bread_record: BreadRecord,
+        moves: MoveCounter,
+    },
     DoChangeLevel(CurrentLevelIndex),
 
+    /// 连接时告诉客户端服务器允许哪些操作
+    Permissions { allow_cheats: bool },
+
+    /// 连接时告诉客户端它坐在哪个座位上
+    Seat { player_id: u8 },
+
+    /// 对战中选关的提议，None 表示提议结束
+    LevelProposal { proposal: Option<LevelProposal> },
+
+    /// 对战中的撤销投票，None 表示投票结束
+    UndoVote { request: Option<UndoRequest> },
+
+    /// 某一关的排行榜（服务器上的权威版本）
+    LeaderboardUpdate {
+        level_index: usize,
+        scores: Vec<Score>,
+    },
+
+    /// 回合制：轮到谁，以及这一回合的秒数
+    TurnUpdate {
+        player_id: u8,
+        seconds: Option<f32>,
+    },
+
+    /// 竞速：开局倒计时
+    RaceCountdown { seconds: u32 },
+    /// 竞速：开始计时，seconds 是这一局的时限
+    RaceStart { seconds: f32 },
+    /// 竞速结束，winner 为 None 表示平局，cleared 表示面包都被吃完了
+    RaceOver {
+        winner: Option<u8>,
+        player1_bread: u32,
+        player2_bread: u32,
+        elapsed: f32,
+        cleared: bool,
+    },
+
 
 }
+/// 等待对手同意的撤销或重做
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UndoRequest {
+    pub player_id: u8,
+    pub redo: bool,
+}
+
+/// 等待对手同意的关卡
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelProposal {
+    pub player_id: u8,
+    pub level_index: usize,
+}
+
 /// 完整游戏状态
 #[derive(Debug,Default,Clone, Serialize, Deserialize, Resource)]
 pub struct FullGameState {
@@ -126,6 +210,12 @@ pub struct FullGameState {
 
 pub mod server;
 pub mod client;
+pub mod host;
+pub mod local;
+pub mod ai;
+pub mod turns;
+pub mod race;
+pub mod undo;
 
 #[derive(Resource, Default)]
 pub struct SelectionState {
@@ -139,4 +229,89 @@ pub struct SelectionState {
 pub struct RemotePlayerMove {
     pub player_id: u8,
     pub direction: Direction,
+    pub use_ability: bool,
+}
+
+/// 收到的服务器消息，来自网络或本地离线服务器
+#[derive(Event, Clone, Debug)]
+pub struct InboundServerMessage(pub ServerMessage);
+
+/// 离线模式下发给本地服务器的消息
+#[derive(Event, Clone, Debug)]
+pub struct LocalClientMessage(pub ClientMessage);
+
+/// 服务器自己发起的广播（比如回合超时），不对应任何客户端消息
+#[derive(Event, Clone, Debug)]
+pub struct ServerBroadcast(pub ServerMessage);
+
+/// 对局规则，由服务器（或本地服务器）执行
+#[derive(Resource, Default, Clone, Debug)]
+pub struct MatchRules {
+    /// 两个玩家轮流移动
+    pub turn_based: bool,
+    /// 每回合的秒数，超时后换人
+    pub turn_clock: Option<f32>,
+    /// 竞速模式每关的秒数
+    pub race_secs: Option<f32>,
+    /// 联网时也允许 [ ] 跳关（--allow-cheats）
+    pub allow_cheats: bool,
+}
+
+impl MatchRules {
+    // 单人模式没有对手，不分回合；录像也不受这些规则限制
+    pub fn is_turn_based(&self, play_mode: PlayMode) -> bool {
+        self.turn_based && matches!(play_mode, PlayMode::Online | PlayMode::Local)
+    }
+
+    pub fn is_racing(&self, play_mode: PlayMode) -> bool {
+        self.race_secs.is_some() && matches!(play_mode, PlayMode::Online | PlayMode::Local)
+    }
+}
+
+/// 客户端看到的权限，由 Permissions 消息更新
+#[derive(Resource, Default, Clone, Copy, Debug)]
+pub struct Permissions {
+    pub allow_cheats: bool,
+}
+
+/// 联网时这个客户端的座位，由 Seat 消息更新，收到之前是 None
+#[derive(Resource, Default, Clone, Copy, Debug)]
+pub struct LocalSeat(pub Option<u8>);
+
+/// 运行条件：可以用 [ ] 跳关。离线时本进程就是服务器，总是允许
+pub fn cheats_allowed(play_mode: Res<PlayMode>, permissions: Option<Res<Permissions>>) -> bool {
+    play_mode.is_offline() || permissions.is_some_and(|permissions| permissions.allow_cheats)
+}
+
+/// 服务器内部的虚拟客户端（AI）发出的消息，和真人消息走同一条处理路径
+#[derive(Event, Clone, Debug)]
+pub struct VirtualClientMessage {
+    pub player_id: u8,
+    pub message: ClientMessage,
+}
+
+/// 把客户端消息发给服务器：联网时走 RenetClient，离线时交给本地服务器
+#[derive(SystemParam)]
+pub struct ServerLink<'w> {
+    play_mode: Res<'w, PlayMode>,
+    client: Option<ResMut<'w, RenetClient>>,
+    local: EventWriter<'w, LocalClientMessage>,
+}
+
+impl ServerLink<'_> {
+    pub fn send(&mut self, message: ClientMessage) {
+        match *self.play_mode {
+            PlayMode::Online => {
+                if let Some(client) = &mut self.client {
+                    let message = bincode::serialize(&message).unwrap();
+                    client.send_message(0, message); // 使用可靠通道
+                }
+            }
+            PlayMode::Local | PlayMode::Solo => {
+                self.local.send(LocalClientMessage(message));
+            }
+            // 录像只重放记录下来的移动
+            PlayMode::Replay => {}
+        }
+    }
 }
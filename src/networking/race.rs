@@ -0,0 +1,292 @@
+// src/networking/race.rs
+// 竞速模式：服务器倒计时开局，限时内面包多的一方获胜
+use bevy::prelude::*;
+
+use super::host::is_host;
+use super::*;
+use crate::game::level::BreadCount;
+use crate::game::player::CommonDuck;
+use crate::game::progress::LevelProgress;
+use crate::game::MY_ORANGE;
+
+pub struct RacePlugin;
+
+impl Plugin for RacePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RaceState>()
+            .init_resource::<RaceClock>()
+            .add_systems(
+                Update,
+                tick_race
+                    .run_if(in_state(GameStates::Next))
+                    .run_if(is_host)
+                    .run_if(is_racing),
+            )
+            .add_systems(OnEnter(GameStates::Next), show_race_clock)
+            .add_systems(
+                Update,
+                (
+                    // 开局的倒计时可能在切换到 Next 之前就到了
+                    apply_race_messages,
+                    update_race_clock.run_if(in_state(GameStates::Next)),
+                )
+                    .chain(),
+            )
+            .add_systems(OnExit(GameStates::Next), cleanup_race_clock);
+    }
+}
+
+// 开局倒计时秒数
+const COUNTDOWN_SECS: u32 = 3;
+
+/// 服务器端的竞速进度
+#[derive(Resource, Default)]
+pub enum RaceState {
+    #[default]
+    Idle,
+    Countdown {
+        seconds_left: u32,
+        tick: Timer,
+    },
+    Running {
+        clock: Timer,
+    },
+    Over,
+}
+
+impl RaceState {
+    /// 开始新一局的倒计时，返回要广播的消息
+    pub fn start(&mut self) -> ServerMessage {
+        *self = RaceState::Countdown {
+            seconds_left: COUNTDOWN_SECS,
+            tick: Timer::from_seconds(1.0, TimerMode::Repeating),
+        };
+        ServerMessage::RaceCountdown { seconds: COUNTDOWN_SECS }
+    }
+
+    /// 倒计时和结束后都不能移动
+    pub fn accepts_moves(&self) -> bool {
+        matches!(self, RaceState::Running { .. })
+    }
+}
+
+fn is_racing(rules: Res<MatchRules>, play_mode: Res<PlayMode>) -> bool {
+    rules.is_racing(*play_mode)
+}
+
+fn tick_race(
+    time: Res<Time>,
+    rules: Res<MatchRules>,
+    bread_count: Res<BreadCount>,
+    mut race: ResMut<RaceState>,
+    duck_query: Query<&CommonDuck>,
+    mut broadcast: EventWriter<ServerBroadcast>,
+) {
+    match &mut *race {
+        RaceState::Countdown { seconds_left, tick } => {
+            if !tick.tick(time.delta()).just_finished() {
+                return;
+            }
+            *seconds_left -= 1;
+            if *seconds_left > 0 {
+                broadcast.send(ServerBroadcast(ServerMessage::RaceCountdown {
+                    seconds: *seconds_left,
+                }));
+            } else {
+                let seconds = rules.race_secs.unwrap_or_default();
+                *race = RaceState::Running {
+                    clock: Timer::from_seconds(seconds, TimerMode::Once),
+                };
+                broadcast.send(ServerBroadcast(ServerMessage::RaceStart { seconds }));
+            }
+        }
+        RaceState::Running { clock } => {
+            clock.tick(time.delta());
+            let cleared = bread_count.0 <= 0;
+            if !cleared && !clock.finished() {
+                return;
+            }
+            // 每个玩家所有鸭子吃的面包加起来
+            let bread_of = |player_id: u8| {
+                duck_query
+                    .iter()
+                    .filter(|duck| duck.owner == player_id)
+                    .map(|duck| duck.bread_count)
+                    .sum::<u32>()
+            };
+            let player1_bread = bread_of(1);
+            let player2_bread = bread_of(2);
+            let winner = match player1_bread.cmp(&player2_bread) {
+                std::cmp::Ordering::Greater => Some(1),
+                std::cmp::Ordering::Less => Some(2),
+                std::cmp::Ordering::Equal => None,
+            };
+            let elapsed = clock.elapsed_secs();
+            info!("Race over: winner {:?}, {} vs {} in {:.1}s", winner, player1_bread, player2_bread, elapsed);
+            broadcast.send(ServerBroadcast(ServerMessage::RaceOver {
+                winner,
+                player1_bread,
+                player2_bread,
+                elapsed,
+                cleared,
+            }));
+            *race = RaceState::Over;
+        }
+        RaceState::Idle | RaceState::Over => {}
+    }
+}
+
+/// 客户端看到的竞速状态，由服务器消息驱动
+#[derive(Resource, Default)]
+pub enum RaceClock {
+    #[default]
+    Off,
+    Countdown(u32),
+    Running(Timer),
+    Over {
+        winner: Option<u8>,
+        player1_bread: u32,
+        player2_bread: u32,
+        elapsed: f32,
+    },
+}
+
+fn apply_race_messages(
+    mut inbound: EventReader<InboundServerMessage>,
+    mut race_clock: ResMut<RaceClock>,
+    level_index: Res<CurrentLevelIndex>,
+    mut progress: ResMut<LevelProgress>,
+) {
+    for InboundServerMessage(message) in inbound.read() {
+        match *message {
+            ServerMessage::RaceCountdown { seconds } => {
+                *race_clock = RaceClock::Countdown(seconds);
+            }
+            ServerMessage::RaceStart { seconds } => {
+                *race_clock = RaceClock::Running(Timer::from_seconds(seconds, TimerMode::Once));
+            }
+            ServerMessage::RaceOver {
+                winner,
+                player1_bread,
+                player2_bread,
+                elapsed,
+                cleared,
+            } => {
+                // 只有吃完所有面包才算通关时间
+                if cleared {
+                    progress.record_time(level_index.0, elapsed);
+                }
+                *race_clock = RaceClock::Over {
+                    winner,
+                    player1_bread,
+                    player2_bread,
+                    elapsed,
+                };
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct RaceClockText;
+
+#[derive(Component)]
+pub struct RaceBanner;
+
+fn show_race_clock(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    query: Query<Entity, With<RaceClockText>>,
+) {
+    if !query.is_empty() {
+        return;
+    }
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/NotJamChunky8.ttf"),
+                font_size: 30.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            right: Val::Px(10.0),
+            ..default()
+        }),
+        RaceClockText,
+    ));
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/NotJamChunky8.ttf"),
+                font_size: 40.0,
+                color: MY_ORANGE,
+            },
+        )
+        .with_text_justify(JustifyText::Center)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(100.0),
+            left: Val::Px(0.0),
+            right: Val::Px(0.0),
+            ..default()
+        }),
+        RaceBanner,
+    ));
+}
+
+fn update_race_clock(
+    time: Res<Time>,
+    mut race_clock: ResMut<RaceClock>,
+    mut clock_query: Query<&mut Text, (With<RaceClockText>, Without<RaceBanner>)>,
+    mut banner_query: Query<&mut Text, (With<RaceBanner>, Without<RaceClockText>)>,
+) {
+    if let RaceClock::Running(clock) = &mut *race_clock {
+        clock.tick(time.delta());
+    }
+    let (clock_value, banner_value) = match &*race_clock {
+        RaceClock::Off => (String::new(), String::new()),
+        RaceClock::Countdown(seconds) => (String::new(), seconds.to_string()),
+        RaceClock::Running(clock) => (format_clock(clock.remaining_secs()), String::new()),
+        RaceClock::Over {
+            winner,
+            player1_bread,
+            player2_bread,
+            elapsed,
+        } => {
+            let result = match winner {
+                Some(player_id) => format!("P{} wins!", player_id),
+                None => "Draw!".to_string(),
+            };
+            (
+                format_clock(*elapsed),
+                format!("{} {} : {}", result, player1_bread, player2_bread),
+            )
+        }
+    };
+    for mut text in clock_query.iter_mut() {
+        text.sections[0].value = clock_value.clone();
+    }
+    for mut text in banner_query.iter_mut() {
+        text.sections[0].value = banner_value.clone();
+    }
+}
+
+fn format_clock(seconds: f32) -> String {
+    let seconds = seconds.ceil() as u32;
+    format!("{:02}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn cleanup_race_clock(
+    mut commands: Commands,
+    query: Query<Entity, Or<(With<RaceClockText>, With<RaceBanner>)>>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+}
@@ -6,9 +6,9 @@ use bevy_renet::{
 };
 use std::{net::UdpSocket};
 use crate::{networking::SelectionState};
-use crate::game::level::{Level,Levels};
 use super::*;
-use renet::ServerEvent;
+use renet::{ClientId, ServerEvent};
+use std::collections::HashMap;
 use std::time::SystemTime;
 use renet::ConnectionConfig;
 pub struct ServerPlugin;
@@ -19,13 +19,65 @@ impl Plugin for ServerPlugin {
             .add_plugins(bevy_renet::transport::NetcodeServerPlugin)
             .init_resource::<ServerChannels>()
             .init_resource::<SelectionState>()
+            .init_resource::<Seats>()
             .add_systems(OnEnter(GameStates::Loading), setup_server)
-            .add_systems(Update, (handle_client_messages, handle_server_events));
+            .add_systems(
+                Update,
+                (
+                    handle_client_messages,
+                    handle_server_events,
+                    client::handle_server_messages.after(handle_client_messages),
+                    (record_server_score, record_match_progress).run_if(in_state(GameStates::Next)),
+                ),
+            );
     }
 }
 
-fn setup_server(mut commands: Commands) {
-    let server_addr = "0.0.0.0:5000".parse().unwrap();
+/// 每个连接坐在哪个座位上。连上时分配，断开时空出来，
+/// 不能按 `clients_id()` 的顺序算，它的顺序是不固定的
+#[derive(Resource, Default)]
+pub struct Seats {
+    seats: HashMap<ClientId, u8>,
+    /// 开服务器的玩家：第一个从本机连上来的连接，只有他能作弊
+    host: Option<ClientId>,
+}
+
+impl Seats {
+    /// 给新连接分配第一个空着的座位，`local` 表示从本机连上来
+    fn assign(&mut self, client_id: ClientId, local: bool) -> u8 {
+        let player_id = (1..=2)
+            .find(|seat| !self.seats.values().any(|taken| taken == seat))
+            .unwrap_or(2);
+        self.seats.insert(client_id, player_id);
+        if local && self.host.is_none() {
+            self.host = Some(client_id);
+        }
+        player_id
+    }
+
+    fn free(&mut self, client_id: ClientId) {
+        self.seats.remove(&client_id);
+        if self.host == Some(client_id) {
+            self.host = None;
+        }
+    }
+
+    fn get(&self, client_id: ClientId) -> Option<u8> {
+        self.seats.get(&client_id).copied()
+    }
+
+    fn is_host(&self, client_id: ClientId) -> bool {
+        self.host == Some(client_id)
+    }
+
+    /// 开服务器的玩家坐的座位
+    pub fn host_seat(&self) -> Option<u8> {
+        self.host.and_then(|client_id| self.get(client_id))
+    }
+}
+
+fn setup_server(mut commands: Commands, mut leaderboard: ResMut<ServerLeaderboard>) {
+    let server_addr = format!("0.0.0.0:{}", DEFAULT_PORT).parse().unwrap();
     let socket = UdpSocket::bind(server_addr).unwrap();
     let connection_config = ConnectionConfig::default();
     let server_config = ServerConfig {
@@ -45,194 +97,85 @@ fn setup_server(mut commands: Commands) {
     commands.insert_resource(transport);
     commands.insert_resource(FullGameState::default());
 
+    if let Some(saved) = storage::load::<Leaderboard>(SERVER_LEADERBOARD_FILE) {
+        leaderboard.0 = saved;
+    }
+
     info!("Server started on {:?}", server_addr);
 }
 
-use crate::game::level::LevelStack;
-use crate::game::level::BreadSumRecordStack;
+use super::ai::{is_reserved_for_ai, AiOpponent};
+use super::race::RaceClock;
+use crate::game::leaderboard::{race_seconds, Leaderboard, Score, ServerLeaderboard, SERVER_LEADERBOARD_FILE};
+use crate::game::level::{CurrentLevelIndex, LevelCleared};
+use crate::game::progress::MoveCounter;
+use crate::game::storage;
+use super::host::{process_client_message, HostContext, MatchProgress, Outgoing};
 fn handle_client_messages(
     mut server: ResMut<RenetServer>,
-    mut selection_state: ResMut<SelectionState>,
-    mut game_state: ResMut<FullGameState>,
     server_channels: Res<ServerChannels>,
-    mut next_state: ResMut<NextState<GameStates>>,
-    levels: Res<Levels>,
-    mut level: ResMut<Level>,
-    mut level_stack: ResMut<LevelStack>,
-    mut bread_sum_record_stack: ResMut<BreadSumRecordStack>,
-    /* 
-    mut bread_count: ResMut<BreadCount>,
-    mut level_index: ResMut<CurrentLevelIndex>,
-    mut events_update: EventWriter<UpdateLevel>,*/
+    mut virtual_messages: EventReader<VirtualClientMessage>,
+    mut broadcasts: EventReader<ServerBroadcast>,
+    mut inbound: EventWriter<InboundServerMessage>,
+    ai: Option<Res<AiOpponent>>,
+    seats: Res<Seats>,
+    mut host: HostContext,
 ) {
     for client_id in server.clients_id().into_iter() {
         //info!("start handle message");
+        let Some(player_id) = seats.get(client_id) else {
+            continue;
+        };
         // 处理可靠消息
         while let Some(message) = server.receive_message(client_id, server_channels.reliable_ordered) {
             info!("receive message from:{}",client_id);
             if let Ok(client_message) = bincode::deserialize::<ClientMessage>(&message) {
-                match client_message {
-                    ClientMessage::StateChangeRequest(new_state) => {
-                        game_state.current_state = new_state;
-                        next_state.set(new_state);
-                        
-                        // 广播新状态给所有客户端
-                        let message = bincode::serialize(&ServerMessage::StateChangeNotification(
-                            new_state,
-                        ))
-                        .unwrap();
-                        server.broadcast_message(server_channels.reliable_ordered, message);
-                        
-                        info!("State changed to: {:?}", new_state);
-                        
-                    }
-                    ClientMessage::PlayerPositionUpdate(position) => {
-                        // 更新玩家位置并广播
-                        let player_id = if server.clients_id()[0] == client_id { 1 } else { 2 };
-                        
-                        if player_id == 1 {
-                            game_state.player1_position = position;
-                        } else {
-                            game_state.player2_position = position;
-                        }
-                        
-                        let message = bincode::serialize(&ServerMessage::PlayerPositionUpdate {
-                            player_id,
-                            position,
-                        })
-                        .unwrap();
-                        server.broadcast_message(server_channels.unreliable, message);
-                    }
-                    
-                    ClientMessage::RequestFullState => {
-                        // 发送完整状态给请求的客户端
-                        let message = bincode::serialize(&ServerMessage::FullStateSync(
-                            game_state.clone(),
-                        ))
-                        .unwrap();
-                        server.send_message(client_id, server_channels.reliable_ordered, message);
-                    }
-                    ClientMessage::CharacterSelected { player_id, character } => {
-                        match player_id {
-                            1 => {
-                                selection_state.player1_choice = Some(character);
-                                info!("Player 1 selected {:?}", character);
-                            },
-                            2 => {
-                                selection_state.player2_choice = Some(character);
-                                info!("Player 2 selected {:?}", character);
-                            },
-                            _ => {}
-                        }
-                        
-                        // 广播选择更新给所有客户端
-                        let update = ServerMessage::CharacterSelectionUpdate {
-                            player1_choice: selection_state.player1_choice,
-                            player2_choice: selection_state.player2_choice,
-                        };
-                        let message = bincode::serialize(&update).unwrap();
-                        server.broadcast_message(0, message);
-                    },
-
-                    ClientMessage::ReadyForGameStart => {
-                        let player_id = if server.clients_id()[0] == client_id { 1 } else { 2 };
-                        match player_id {
-                            1 => {
-                                selection_state.player1_ready = true;
-                                info!("Player 1 is ready");
-                            },
-                            2 => {
-                                selection_state.player2_ready = true;
-                                info!("Player 2 is ready");
-                            },
-                            _ => {}
-                        }
-                        
-                        // 检查是否都准备好了
-                        if selection_state.player1_ready && selection_state.player2_ready {
-                            info!("Both players ready. p1_choice: {:?}, p2_choice: {:?}", selection_state.player1_choice, selection_state.player2_choice);    
-                            if let (Some(p1_char), Some(p2_char)) = (
-                                selection_state.player1_choice,
-                                selection_state.player2_choice
-                            ) {
-                                // 通知所有客户端开始游戏
-                                let start_msg = ServerMessage::StartGameWithCharacters {
-                                    player1_character: p1_char,
-                                    player2_character: p2_char,
-                                };
-                                info!("start!");
-                                let message = bincode::serialize(&start_msg).unwrap();
-                                server.broadcast_message(0, message);
-                                
-                                // 更新服务器状态
-                                game_state.player1_character = p1_char;
-                                game_state.player2_character = p2_char;
-                                next_state.set(GameStates::Next);
-                            }
-                        }
-                    },
-
-                    //movement
-                    ClientMessage::PlayerMovementInput { player_id, direction } => {
-                        let msg = ServerMessage::PlayerMovementUpdate { player_id, direction };
-                        let serialized = bincode::serialize(&msg).unwrap();
-                        server.broadcast_message(server_channels.reliable_ordered, serialized);
-                    }
-
-                    ClientMessage::NextLevelRequest => {
-                        game_state.current_level.0 += 1;
-
-                        let new_level = game_state.current_level;
-
-                        info!("Server: advancing to level {}", new_level.0);
-
-                        let message = bincode::serialize(&ServerMessage::NextLevelNotification {
-                            level_index: new_level,
-                        }).unwrap();
-
-                        server.broadcast_message(server_channels.reliable_ordered, message);
-                        
-                    }
-
-                    ClientMessage::RestartLevel => {
-                        let msg = ServerMessage::DoRestartLevel;
-                        let serialized = bincode::serialize(&msg).unwrap();
-                        server.broadcast_message(server_channels.reliable_ordered, serialized);
-                    }
-
-                    ClientMessage::UndoLevel => {
-                        info!("Undo request from client {}", client_id);
-
-                        // 至少有两帧状态才能撤销（当前和之前）
-                        if level_stack.0.size() >= 2 {
-                            // 撤销 Level 和 Bread 状态
-                            level_stack.0.pop();
-                            let popped_level = level_stack.0.peek().unwrap().clone();
-                            level.0 = popped_level.clone();
-
-                            if bread_sum_record_stack.0.size() >= 1 {
-                                bread_sum_record_stack.0.pop();
-                            }
-
-                            let msg = bincode::serialize(&ServerMessage::DoUndoLevel(Level(popped_level))).unwrap();
-                            server.broadcast_message(server_channels.reliable_ordered, msg);
-                            info!("Server broadcasted undo level");
-                        } else {
-                            info!("Undo rejected: level stack too shallow");
-                        }
-                    }
-                    
-
-                    ClientMessage::ChangeLevelCheat(index) => {
+                if is_reserved_for_ai(&client_message, ai.as_deref()) {
+                    continue;
+                }
+                for outgoing in process_client_message(player_id, client_message, &mut host) {
+                    dispatch(&mut server, &server_channels, Some(client_id), outgoing, &mut inbound);
+                }
+            }
+        }
+    }
 
-                        let msg = ServerMessage::DoChangeLevel(index);
-                        let serialized = bincode::serialize(&msg).unwrap();
-                        server.broadcast_message(server_channels.reliable_ordered, serialized);
-                        info!("change to{}",index.0);
-                    }
+    // 虚拟客户端（AI）的消息
+    for VirtualClientMessage { player_id, message } in virtual_messages.read() {
+        for outgoing in process_client_message(*player_id, message.clone(), &mut host) {
+            dispatch(&mut server, &server_channels, None, outgoing, &mut inbound);
+        }
+    }
 
+    for ServerBroadcast(message) in broadcasts.read() {
+        let outgoing = Outgoing::Broadcast(message.clone());
+        dispatch(&mut server, &server_channels, None, outgoing, &mut inbound);
+    }
+}
 
-                }
+// 广播的消息服务器自己也执行一遍，让服务器上的关卡和鸭子保持权威状态
+fn dispatch(
+    server: &mut RenetServer,
+    server_channels: &ServerChannels,
+    sender: Option<ClientId>,
+    outgoing: Outgoing,
+    inbound: &mut EventWriter<InboundServerMessage>,
+) {
+    match outgoing {
+        Outgoing::Broadcast(message) => {
+            let bytes = bincode::serialize(&message).unwrap();
+            server.broadcast_message(server_channels.reliable_ordered, bytes);
+            inbound.send(InboundServerMessage(message));
+        }
+        Outgoing::BroadcastUnreliable(message) => {
+            let bytes = bincode::serialize(&message).unwrap();
+            server.broadcast_message(server_channels.unreliable, bytes);
+            inbound.send(InboundServerMessage(message));
+        }
+        Outgoing::Reply(message) => {
+            if let Some(client_id) = sender {
+                let bytes = bincode::serialize(&message).unwrap();
+                server.send_message(client_id, server_channels.reliable_ordered, bytes);
             }
         }
     }
@@ -243,6 +186,9 @@ fn handle_server_events(
     mut server_events: EventReader<ServerEvent>,
     server_channels: Res<ServerChannels>,
     game_state: ResMut<FullGameState>,
+    rules: Res<MatchRules>,
+    transport: Res<NetcodeServerTransport>,
+    mut seats: ResMut<Seats>,
 ) {
     for event in server_events.read() {
         match event {
@@ -250,7 +196,10 @@ fn handle_server_events(
                 info!("Client {} connected", client_id);
                 
                 // 分配玩家ID
-                let player_id = if server.clients_id().len() == 1 { 1 } else { 2 };
+                let local = transport
+                    .client_addr(*client_id)
+                    .is_some_and(|addr| addr.ip().is_loopback());
+                let player_id = seats.assign(*client_id, local);
                 info!("Assigned Player {} to client {}", player_id, client_id);
                 
                 // 发送完整状态给新客户端
@@ -259,11 +208,139 @@ fn handle_server_events(
                 ))
                 .unwrap();
                 server.send_message(*client_id, server_channels.reliable_ordered, message);
+
+                let message = bincode::serialize(&ServerMessage::Permissions {
+                    allow_cheats: rules.allow_cheats && seats.is_host(*client_id),
+                })
+                .unwrap();
+                server.send_message(*client_id, server_channels.reliable_ordered, message);
+
+                let message = bincode::serialize(&ServerMessage::Seat { player_id }).unwrap();
+                server.send_message(*client_id, server_channels.reliable_ordered, message);
             }
             ServerEvent::ClientDisconnected { client_id, reason } => {
                 info!("Client {} disconnected: {}", client_id, reason);
-                // 可以在这里处理玩家断开后的逻辑
+                seats.free(*client_id);
             }
         }
     }
 }
+
+// 通关的关卡解锁下一关，选关时检查
+fn record_match_progress(
+    mut events: EventReader<LevelCleared>,
+    level_index: Res<CurrentLevelIndex>,
+    move_counter: Res<MoveCounter>,
+    mut match_progress: ResMut<MatchProgress>,
+) {
+    for _ in events.read() {
+        match_progress.0.record(level_index.0, move_counter.total());
+    }
+}
+
+// 服务器上的排行榜是权威版本，记录后广播给所有客户端
+fn record_server_score(
+    mut events: EventReader<LevelCleared>,
+    level_index: Res<CurrentLevelIndex>,
+    move_counter: Res<MoveCounter>,
+    race_clock: Res<RaceClock>,
+    game_state: Res<FullGameState>,
+    mut leaderboard: ResMut<ServerLeaderboard>,
+    mut broadcast: EventWriter<ServerBroadcast>,
+) {
+    for _ in events.read() {
+        leaderboard.0.submit(
+            level_index.0,
+            Score {
+                name: format!("{:?} & {:?}", game_state.player1_character, game_state.player2_character),
+                moves: move_counter.total(),
+                seconds: race_seconds(&race_clock),
+            },
+        );
+        if let Err(e) = storage::save(SERVER_LEADERBOARD_FILE, &leaderboard.0) {
+            warn!("Failed to save server leaderboard: {}", e);
+        }
+        broadcast.send(ServerBroadcast(ServerMessage::LeaderboardUpdate {
+            level_index: level_index.0,
+            scores: leaderboard.0.top(level_index.0).to_vec(),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::ai::{AiDifficulty, AiOpponent, AI_PLAYER_ID};
+    use crate::networking::host::tests::{host_app, process};
+    use crate::networking::local::LocalServerPlugin;
+
+    #[test]
+    fn ai_move_is_applied_once_on_a_server() {
+        let mut app = host_app(PlayMode::Online);
+        app.add_plugins(LocalServerPlugin)
+            .init_resource::<ServerChannels>()
+            .insert_resource(RenetServer::new(ConnectionConfig::default()))
+            .insert_resource(AiOpponent::new(AiDifficulty::Easy))
+            .init_resource::<Seats>()
+            .add_systems(Update, handle_client_messages);
+        app.world_mut().send_event(VirtualClientMessage {
+            player_id: AI_PLAYER_ID,
+            message: ClientMessage::PlayerMovementInput {
+                player_id: AI_PLAYER_ID,
+                direction: crate::game::utils::Direction::Left,
+                use_ability: false,
+            },
+        });
+        app.update();
+
+        let events = app.world().resource::<Events<InboundServerMessage>>();
+        let moves = events
+            .get_reader()
+            .read(events)
+            .filter(|InboundServerMessage(message)| matches!(message, ServerMessage::PlayerMovementUpdate { .. }))
+            .count();
+        assert_eq!(moves, 1);
+    }
+
+    #[test]
+    fn seats_follow_connections_not_client_order() {
+        let (first, second, third) = (ClientId::from_raw(30), ClientId::from_raw(10), ClientId::from_raw(20));
+        let mut seats = Seats::default();
+        assert_eq!(seats.assign(first, false), 1);
+        assert_eq!(seats.assign(second, false), 2);
+        // 空出来的座位给下一个连上的人
+        seats.free(first);
+        assert_eq!(seats.assign(third, false), 1);
+        assert_eq!((seats.get(second), seats.get(first)), (Some(2), None));
+    }
+
+    #[test]
+    fn host_is_the_first_local_connection() {
+        let (remote, local, other_local) = (ClientId::from_raw(1), ClientId::from_raw(2), ClientId::from_raw(3));
+        let mut seats = Seats::default();
+        seats.assign(remote, false);
+        seats.assign(local, true);
+        assert_eq!(seats.host_seat(), Some(2));
+        seats.free(local);
+        assert_eq!(seats.host_seat(), None);
+        seats.assign(other_local, true);
+        assert_eq!(seats.host_seat(), Some(2));
+    }
+
+    #[test]
+    fn only_the_host_can_cheat() {
+        let mut app = host_app(PlayMode::Online);
+        let mut seats = Seats::default();
+        seats.assign(ClientId::from_raw(1), false);
+        seats.assign(ClientId::from_raw(2), true);
+        app.insert_resource(seats).insert_resource(MatchRules {
+            allow_cheats: true,
+            ..default()
+        });
+        let cheat = ClientMessage::ChangeLevelCheat(CurrentLevelIndex(5));
+        assert!(process(&mut app, 1, cheat.clone()).is_empty());
+        assert!(process(&mut app, 1, ClientMessage::SelectLevel(CurrentLevelIndex(5))).is_empty());
+        let replies = process(&mut app, 2, cheat);
+        assert!(matches!(replies[0], ServerMessage::DoChangeLevel(CurrentLevelIndex(5))));
+    }
+}
@@ -0,0 +1,167 @@
+// src/networking/turns.rs
+// 回合制：服务器记录轮到谁，拒绝不是自己回合的移动，可选每回合限时
+use bevy::prelude::*;
+
+use super::host::is_host;
+use super::*;
+use crate::game::level::BreadCount;
+use crate::game::MY_ORANGE;
+use crate::game::player::CommonDuck;
+
+pub struct TurnPlugin;
+
+impl Plugin for TurnPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MatchRules>()
+            .init_resource::<TurnState>()
+            .init_resource::<CurrentTurn>()
+            .add_systems(
+                Update,
+                (tick_turn_clock, skip_stuck_player)
+                    .run_if(in_state(GameStates::Next))
+                    .run_if(is_host)
+                    .run_if(is_turn_based),
+            )
+            .add_systems(OnEnter(GameStates::Next), show_turn_indicator)
+            .add_systems(Update, update_turn_indicator.run_if(in_state(GameStates::Next)))
+            .add_systems(OnExit(GameStates::Next), cleanup_turn_indicator);
+    }
+}
+
+/// 服务器端：当前回合和剩余时间
+#[derive(Resource)]
+pub struct TurnState {
+    pub current: u8,
+    clock: Option<Timer>,
+}
+
+impl Default for TurnState {
+    fn default() -> Self {
+        TurnState { current: 1, clock: None }
+    }
+}
+
+impl TurnState {
+    /// 把回合交给 `player_id`，返回要广播的消息
+    pub fn start(&mut self, player_id: u8, rules: &MatchRules) -> ServerMessage {
+        self.current = player_id;
+        self.clock = rules
+            .turn_clock
+            .map(|seconds| Timer::from_seconds(seconds, TimerMode::Once));
+        ServerMessage::TurnUpdate {
+            player_id,
+            seconds: rules.turn_clock,
+        }
+    }
+
+    /// 每关都从 Player1 开始
+    pub fn reset(&mut self, rules: &MatchRules) -> ServerMessage {
+        self.start(1, rules)
+    }
+
+    pub fn pass(&mut self, rules: &MatchRules) -> ServerMessage {
+        self.start(3 - self.current, rules)
+    }
+}
+
+/// 客户端看到的回合，由 TurnUpdate 更新
+#[derive(Resource, Default)]
+pub struct CurrentTurn {
+    pub player_id: Option<u8>,
+    pub clock: Option<Timer>,
+}
+
+// 联网时由服务器计时，离线时由本地服务器计时
+fn is_turn_based(rules: Res<MatchRules>, play_mode: Res<PlayMode>) -> bool {
+    rules.is_turn_based(*play_mode)
+}
+
+fn tick_turn_clock(
+    time: Res<Time>,
+    rules: Res<MatchRules>,
+    bread_count: Res<BreadCount>,
+    mut turn: ResMut<TurnState>,
+    mut broadcast: EventWriter<ServerBroadcast>,
+) {
+    // 面包吃完了就不再计时
+    if bread_count.0 <= 0 {
+        return;
+    }
+    let Some(clock) = &mut turn.clock else {
+        return;
+    };
+    if clock.tick(time.delta()).just_finished() {
+        info!("Player {} ran out of time", turn.current);
+        let message = turn.pass(&rules);
+        broadcast.send(ServerBroadcast(message));
+    }
+}
+
+// 当前玩家的鸭子全都被困住时直接跳过
+fn skip_stuck_player(
+    rules: Res<MatchRules>,
+    mut turn: ResMut<TurnState>,
+    duck_query: Query<&CommonDuck>,
+    mut broadcast: EventWriter<ServerBroadcast>,
+) {
+    let can_move = |player_id: u8| duck_query.iter().any(|duck| duck.owner == player_id && duck.can_move);
+    if !can_move(turn.current) && can_move(3 - turn.current) {
+        let message = turn.pass(&rules);
+        broadcast.send(ServerBroadcast(message));
+    }
+}
+
+#[derive(Component)]
+pub struct TurnIndicator;
+
+fn show_turn_indicator(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    query: Query<Entity, With<TurnIndicator>>,
+) {
+    if !query.is_empty() {
+        return;
+    }
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/NotJamChunky8.ttf"),
+                font_size: 30.0,
+                color: MY_ORANGE,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        TurnIndicator,
+    ));
+}
+
+fn update_turn_indicator(
+    time: Res<Time>,
+    mut current_turn: ResMut<CurrentTurn>,
+    mut query: Query<&mut Text, With<TurnIndicator>>,
+) {
+    if let Some(clock) = &mut current_turn.clock {
+        clock.tick(time.delta());
+    }
+    for mut text in query.iter_mut() {
+        text.sections[0].value = match current_turn.player_id {
+            Some(player_id) => match &current_turn.clock {
+                Some(clock) => format!("P{}'s turn {:.0}s", player_id, clock.remaining_secs().ceil()),
+                None => format!("P{}'s turn", player_id),
+            },
+            None => String::new(),
+        };
+    }
+}
+
+fn cleanup_turn_indicator(mut commands: Commands, query: Query<Entity, With<TurnIndicator>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+}
@@ -0,0 +1,180 @@
+// src/networking/undo.rs
+// 对战中的撤销和重做：一方发起请求，对手同意后服务器才执行
+use bevy::prelude::*;
+
+use super::*;
+use crate::game::MY_ORANGE;
+
+pub struct UndoPlugin;
+
+impl Plugin for UndoPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<UndoVote>()
+            .add_systems(
+                Update,
+                (
+                    apply_undo_votes,
+                    (show_undo_dialog, answer_undo_vote).run_if(in_state(GameStates::Next)),
+                )
+                    .chain(),
+            )
+            .add_systems(OnExit(GameStates::Next), cleanup_undo_dialog);
+    }
+}
+
+const NORMAL_BUTTON: Color = MY_ORANGE;
+const HOVERED_BUTTON: Color = Color::srgb(1.0, 0.6, 0.2);
+const PRESSED_BUTTON: Color = Color::srgb(0.75, 0.75, 0.75);
+
+/// 客户端看到的撤销投票，由 UndoVote 消息更新
+#[derive(Resource, Default)]
+pub struct UndoVote {
+    pub request: Option<UndoRequest>,
+}
+
+fn apply_undo_votes(mut inbound: EventReader<InboundServerMessage>, mut vote: ResMut<UndoVote>) {
+    for InboundServerMessage(message) in inbound.read() {
+        if let ServerMessage::UndoVote { request } = message {
+            vote.request = *request;
+        }
+    }
+}
+
+#[derive(Component)]
+struct UndoDialog;
+
+#[derive(Component, Clone, Copy)]
+struct UndoAnswer(bool);
+
+fn show_undo_dialog(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    vote: Res<UndoVote>,
+    query: Query<Entity, With<UndoDialog>>,
+) {
+    if !vote.is_changed() {
+        return;
+    }
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let Some(request) = vote.request else {
+        return;
+    };
+
+    let font = asset_server.load("fonts/NotJamChunky8.ttf");
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    top: Val::Px(160.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(15.0),
+                    ..default()
+                },
+                ..default()
+            },
+            UndoDialog,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                format!(
+                    "P{} wants to {}",
+                    request.player_id,
+                    if request.redo { "redo" } else { "undo" }
+                ),
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 30.0,
+                    color: MY_ORANGE,
+                },
+            ));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(20.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for (label, accept) in [("Accept (Enter)", true), ("Decline (Esc)", false)] {
+                        parent
+                            .spawn((
+                                ButtonBundle {
+                                    style: Style {
+                                        width: Val::Px(260.0),
+                                        height: Val::Px(50.0),
+                                        justify_content: JustifyContent::Center,
+                                        align_items: AlignItems::Center,
+                                        ..default()
+                                    },
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                                UndoAnswer(accept),
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle::from_section(
+                                    label,
+                                    TextStyle {
+                                        font: font.clone(),
+                                        font_size: 20.0,
+                                        color: Color::WHITE,
+                                    },
+                                ));
+                            });
+                    }
+                });
+        });
+}
+
+// 发起人也会看到对话框，拒绝就是撤回自己的请求
+fn answer_undo_vote(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    vote: Res<UndoVote>,
+    mut interaction_query: Query<(&Interaction, &mut BackgroundColor, &UndoAnswer), Changed<Interaction>>,
+    mut link: ServerLink,
+) {
+    if vote.request.is_none() {
+        return;
+    }
+    let mut answer = None;
+    if keyboard.just_pressed(KeyCode::Enter) {
+        answer = Some(true);
+    }
+    if keyboard.just_pressed(KeyCode::Escape) {
+        answer = Some(false);
+    }
+    for (interaction, mut color, UndoAnswer(accept)) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *color = PRESSED_BUTTON.into();
+                answer = Some(*accept);
+            }
+            Interaction::Hovered => {
+                *color = HOVERED_BUTTON.into();
+            }
+            Interaction::None => {
+                *color = NORMAL_BUTTON.into();
+            }
+        }
+    }
+    if let Some(accept) = answer {
+        link.send(ClientMessage::UndoResponse { accept });
+    }
+}
+
+fn cleanup_undo_dialog(
+    mut commands: Commands,
+    mut vote: ResMut<UndoVote>,
+    query: Query<Entity, With<UndoDialog>>,
+) {
+    vote.request = None;
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}